@@ -1,3 +1,4 @@
+use std::env;
 use std::path::PathBuf;
 
 fn main() -> Result<(), String> {
@@ -7,7 +8,12 @@ fn main() -> Result<(), String> {
     println!("cargo:rerun-if-changed={}", resources_dir.display());
 
     vc4_mesa_compiler::build_shaders_dir(&shaders_dir)?;
-    vc4_pack_textures::pack_textures(&resources_dir)?;
+    for input in vc4_pack_textures::pack_textures(&resources_dir)? {
+        println!("cargo:rerun-if-changed={}", input.display());
+    }
+    let out_dir = PathBuf::from(env::var("OUT_DIR").map_err(|err| format!("OUT_DIR: {err}"))?);
+    let atlas_module_path = out_dir.join("atlases.rs");
+    vc4_pack_textures::write_atlas_module(&resources_dir, &atlas_module_path)?;
 
     Ok(())
 }
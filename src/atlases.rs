@@ -0,0 +1,4 @@
+//! Sprite rects of the `resources/*.atlas` directories, generated by the
+//! build script.
+
+include!(concat!(env!("OUT_DIR"), "/atlases.rs"));
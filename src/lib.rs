@@ -1,13 +1,90 @@
+pub mod atlases;
+mod pipeline;
+mod texture;
+pub use pipeline::*;
+pub use texture::*;
+
 use std::collections::HashMap;
-use std::io::Write;
+use std::fs;
+use std::io::{BufReader, Write};
 use std::ops::Range;
+use std::path::Path;
 use std::sync::{Arc, OnceLock};
-use vc4_drm::card::{BufferMapping, Card, SubmitClArgs};
+use vc4_drm::card::{
+    drm_vc4_submit_rcl_surface, BufferMapping, Card, SubmitClArgs, VC4DecimateMode, VC4TilingFormat,
+};
 use vc4_drm::cl::*;
 use vc4_drm::drm::{
     buffer,
-    control::{connector, crtc, framebuffer, Device, Mode, PageFlipFlags},
+    control::{connector, crtc, framebuffer, ClipRect, Device, Mode, PageFlipFlags},
 };
+use vc4_drm::vc4_ctx::{self, CtxError, CtxHeader, CtxTiling};
+
+/// Pixel rectangle with the origin at the top-left of the surface.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Rect {
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn from_size(size: (u16, u16)) -> Self {
+        Self::new(0, 0, size.0, size.1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Saturates at `u16::MAX` for rectangles reaching past it.
+    pub fn right(&self) -> u16 {
+        self.x.saturating_add(self.width)
+    }
+
+    pub fn bottom(&self) -> u16 {
+        self.y.saturating_add(self.height)
+    }
+
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        } else if other.is_empty() {
+            return *self;
+        }
+        let x = u16::min(self.x, other.x);
+        let y = u16::min(self.y, other.y);
+        Rect::new(
+            x,
+            y,
+            u16::max(self.right(), other.right()) - x,
+            u16::max(self.bottom(), other.bottom()) - y,
+        )
+    }
+
+    /// Returns an empty rectangle if the two don't overlap.
+    pub fn intersection(&self, other: &Rect) -> Rect {
+        let x = u16::max(self.x, other.x);
+        let y = u16::max(self.y, other.y);
+        let right = u16::min(self.right(), other.right());
+        let bottom = u16::min(self.bottom(), other.bottom());
+        if right <= x || bottom <= y {
+            Rect::default()
+        } else {
+            Rect::new(x, y, right - x, bottom - y)
+        }
+    }
+}
 
 pub struct Framebuffer {
     pub bo: Buffer,
@@ -48,6 +125,23 @@ impl DisplayFramebuffers {
 
         card.wait_for_flip().await;
     }
+
+    /// Hints KMS that only `rects` of the framebuffer changed since it was
+    /// last presented. Drivers and connectors without damage tracking may
+    /// fail, e.g. with `ENOSYS` or `EINVAL`, in which case the hint can be
+    /// ignored.
+    pub fn dirty(&self, index: usize, rects: &[Rect]) -> std::io::Result<()> {
+        let clips: Vec<ClipRect> = rects
+            .iter()
+            .map(|rect| ClipRect {
+                x1: rect.x,
+                y1: rect.y,
+                x2: rect.right(),
+                y2: rect.bottom(),
+            })
+            .collect();
+        get_card().dirty_framebuffer(self.framebuffers[index].framebuffer, &clips)
+    }
 }
 
 pub fn open_and_allocate_display_framebuffers() -> DisplayFramebuffers {
@@ -177,9 +271,173 @@ pub struct TextureUniform {
     pub config: TextureConfigUniform,
 }
 
+impl TextureUniform {
+    /// Loads a `.ctx` texture or cube map packed by `vc4-pack-textures`,
+    /// with the filtering and wrapping defaults recorded in its header.
+    pub fn from_ctx_file<P: AsRef<Path>>(path: P) -> Result<Self, CtxError> {
+        let mut ctx_f = BufReader::new(fs::File::open(path)?);
+        let header = CtxHeader::read(&mut ctx_f)?;
+
+        // The TMU picks T or LT-format by itself.
+        if header.tiling != CtxTiling::Auto {
+            return Err(CtxError::InvalidField("tiling"));
+        }
+        let data_type = TextureDataType::try_from(header.data_type)
+            .map_err(|_| CtxError::InvalidField("data_type"))?;
+        let mag_filt = TextureMagFilterType::try_from(header.mag_filt)
+            .map_err(|_| CtxError::InvalidField("mag_filt"))?;
+        let min_filt = TextureMinFilterType::try_from(header.min_filt)
+            .map_err(|_| CtxError::InvalidField("min_filt"))?;
+        let wrap_s = TextureWrapType::try_from(header.wrap_s)
+            .map_err(|_| CtxError::InvalidField("wrap_s"))?;
+        let wrap_t = TextureWrapType::try_from(header.wrap_t)
+            .map_err(|_| CtxError::InvalidField("wrap_t"))?;
+        check_ctx_layout(&header, data_type)?;
+
+        let bo = Buffer::new(header.data_size);
+        {
+            let mut mapping = bo.mmap();
+            vc4_ctx::read_data(ctx_f, &header, mapping.as_mut())?;
+        }
+
+        Ok(TextureUniform {
+            buffer: bo,
+            config: TextureConfigUniform {
+                base_address: header.mip0_page_offset,
+                cache_swizzle: 0,
+                cube_map: header.cube_map,
+                flip_y: false,
+                data_type,
+                num_mips: header.num_mips,
+                height: header.height,
+                etc_flip: header.etc_flip,
+                width: header.width,
+                mag_filt,
+                min_filt,
+                wrap_t,
+                wrap_s,
+                cube_map_stride: header.cube_map_stride,
+                border_color: 0,
+                child_image: None,
+            },
+        })
+    }
+}
+
+/// Checks that the sizes of a `.ctx` header describe mip chains laid out
+/// like `vc4-pack-textures` does, within its data, so that the TMU never
+/// reads past the BO.
+fn check_ctx_layout(header: &CtxHeader, data_type: TextureDataType) -> Result<(), CtxError> {
+    use vc4_drm::glam::UVec2;
+    use vc4_drm::vc4_image_addr::MipChainLayout;
+    let size = UVec2::new(header.width as u32, header.height as u32);
+    let num_mips = header.num_mips as u32;
+    let layout = match (data_type, tiled_bpp(data_type)) {
+        (TextureDataType::ETC1, _) => MipChainLayout::try_new_etc1(size, num_mips),
+        (_, Some(bpp)) => MipChainLayout::try_new(size, bpp, num_mips),
+        (_, None) => return Err(CtxError::InvalidField("data_type")),
+    }
+    .map_err(|_| CtxError::InvalidField("width/height"))?;
+
+    if layout.base_page() != header.mip0_page_offset {
+        return Err(CtxError::InvalidField("mip0_page_offset"));
+    }
+    let (faces, face_stride) = if header.cube_map {
+        (6, header.cube_map_stride)
+    } else {
+        (1, layout.total_size)
+    };
+    if layout.total_size > face_stride || face_stride.checked_mul(faces) != Some(header.data_size) {
+        return Err(CtxError::InvalidField("data_size"));
+    }
+    Ok(())
+}
+
+/// Offscreen color (and optional depth) surface for a pass.
+///
+/// The color BO is laid out exactly like a single-level RGBA8888 texture
+/// (T-format, or LT-format for small sizes) so `color` can be bound as a
+/// `ShaderUniform::Texture` once the pass that rendered into it has completed.
+pub struct RenderTarget {
+    pub size: (u16, u16),
+    pub color: TextureUniform,
+    pub depth: Option<Buffer>,
+}
+
+impl RenderTarget {
+    pub fn new(size: (u16, u16), with_depth: bool) -> Self {
+        use vc4_drm::vc4_image_addr::MipChainLayout;
+        let image_size = (size.0 as u32, size.1 as u32);
+        let layout = MipChainLayout::new(image_size.into(), 32, 1);
+        let color = Buffer::new(layout.total_size);
+        let depth = if with_depth {
+            let z_buffer = get_card()
+                .vc4_create_z_buffer(image_size)
+                .expect("unable to create z buffer");
+            Some(Buffer::from_vc4_buffer(z_buffer))
+        } else {
+            None
+        };
+
+        Self {
+            size,
+            color: TextureUniform {
+                buffer: color,
+                config: TextureConfigUniform {
+                    base_address: layout.base_page(),
+                    cache_swizzle: 0,
+                    cube_map: false,
+                    flip_y: false,
+                    data_type: TextureDataType::RGBA8888,
+                    num_mips: 1,
+                    height: size.1,
+                    etc_flip: false,
+                    width: size.0,
+                    mag_filt: TextureMagFilterType::Linear,
+                    min_filt: TextureMinFilterType::Linear,
+                    // NPOT sizes can't repeat.
+                    wrap_t: TextureWrapType::Clamp,
+                    wrap_s: TextureWrapType::Clamp,
+                    cube_map_stride: 0,
+                    border_color: 0,
+                    child_image: None,
+                },
+            },
+            depth,
+        }
+    }
+
+    /// The TMU switches to LT-format for small images, so the render
+    /// surface has to match for the color BO to be sampled correctly.
+    fn color_tiling(&self) -> VC4TilingFormat {
+        use vc4_drm::vc4_image_addr::{Translator, TranslatorTrait};
+        let image_size = (self.size.0 as u32, self.size.1 as u32);
+        if Translator::new(image_size.into(), 32).is_lt_format() {
+            VC4TilingFormat::LT
+        } else {
+            VC4TilingFormat::T
+        }
+    }
+}
+
+/// How a surface's tile buffer contents are initialized at the start of a pass.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LoadOp {
+    /// Clear to a packed value: A8R8G8B8 for color, Z24 for depth.
+    Clear(u32),
+    /// Load the previous contents of the surface.
+    Load,
+    /// Start from undefined contents; every pixel is expected to be drawn.
+    DontCare,
+}
+
 pub enum ShaderUniform<'a> {
     Texture(&'a TextureUniform),
+    /// A texture sampled with an explicit level of detail, e.g. by
+    /// `textureLod`, which also reads the P2 word.
+    TextureLod(&'a TextureUniform),
     Constant(u32),
+    Stencil(&'a StencilConfigUniform),
 }
 
 #[derive(Default)]
@@ -212,6 +470,10 @@ pub struct CommandEncoder {
     bo_handle_map: HashMap<buffer::Handle, u32>,
     bo_handles: Vec<buffer::Handle>,
     window_size: (u16, u16),
+    msaa: bool,
+    damage: Option<Rect>,
+    viewport: Rect,
+    scissor: Option<Rect>,
     width_in_tiles: u8,
     height_in_tiles: u8,
 
@@ -283,12 +545,126 @@ impl CommandEncoder {
         self.window_size
     }
 
+    /// Enables 4x multisampling for subsequent passes. The samples are
+    /// resolved into the color surface at submit; depth and stencil only
+    /// live in the tile buffer and aren't stored.
+    ///
+    /// Must not change between `begin_pass` and `submit`.
+    pub fn set_msaa(&mut self, msaa: bool) {
+        self.msaa = msaa;
+    }
+
+    pub fn msaa(&self) -> bool {
+        self.msaa
+    }
+
+    /// Restricts the next pass to the tiles covering `rects`. Pixels outside
+    /// the damage are clipped, and color is always loaded so that they keep
+    /// their previous contents; the damaged area itself has to be fully
+    /// redrawn. An empty slice renders the whole frame again, while rects
+    /// that are all empty or outside the window leave nothing to draw:
+    /// `damage` is then an empty rect and the next submit is skipped.
+    ///
+    /// Must be called between `clear` and `begin_pass`. With page flipping,
+    /// pass the union of the damage since this buffer was last rendered.
+    /// Not available with MSAA, since that can't load the previous contents.
+    pub fn set_damage(&mut self, rects: &[Rect]) {
+        debug_assert!(!self.msaa, "damage can't be combined with MSAA");
+        // Rects are clipped before their union, so that ones outside the
+        // window don't stretch the bounds over it.
+        let window = Rect::from_size(self.window_size);
+        let bounds = rects.iter().fold(Rect::default(), |bounds, rect| {
+            bounds.union(&rect.intersection(&window))
+        });
+        self.damage = if rects.is_empty() { None } else { Some(bounds) };
+    }
+
+    pub fn damage(&self) -> Option<Rect> {
+        self.damage
+    }
+
+    fn tile_size_in_pixels(&self) -> u16 {
+        if self.msaa {
+            32
+        } else {
+            64
+        }
+    }
+
+    /// Maps normalized device coordinates onto the `width` x `height` pixel
+    /// rectangle at (`x`, `y`) and depth onto `near`..`far`. Primitives are
+    /// also clipped to the viewport.
+    ///
+    /// Shaders read the scale through `vp_x_scale`/`vp_y_scale` when they are
+    /// bound, so set the viewport before binding the shader of a draw.
+    /// `begin_pass` resets the viewport to the whole window.
+    pub fn set_viewport(&mut self, x: u16, y: u16, width: u16, height: u16, near: f32, far: f32) {
+        self.viewport = Rect::new(x, y, width, height);
+
+        // The clipper works in 1/16ths of a pixel.
+        self.set_clipper_xy_scaling(ClipperXYScaling {
+            viewport_half_width_in_1_16th_of_pixel: width as f32 * 16.0 / 2.0,
+            viewport_half_height_in_1_16th_of_pixel: height as f32 * 16.0 / 2.0,
+        });
+
+        self.set_viewport_offset(ViewportOffset {
+            viewport_centre_x_coordinate_12_4: ((x as u32 * 2 + width as u32) * 16 / 2) as u16,
+            viewport_centre_y_coordinate_12_4: ((y as u32 * 2 + height as u32) * 16 / 2) as u16,
+        });
+
+        self.set_clipper_z_scale_and_offset(ClipperZScaleAndOffset {
+            viewport_z_scale_zc_to_zs: (far - near) / 2.0,
+            viewport_z_offset_zc_to_zs: (far + near) / 2.0,
+        });
+
+        self.update_clip_window();
+    }
+
+    pub fn viewport(&self) -> Rect {
+        self.viewport
+    }
+
+    /// Discards pixels outside of `rect`, or only clips to the viewport when
+    /// `None`.
+    ///
+    /// `begin_pass` resets the scissor to `None`.
+    pub fn set_scissor(&mut self, rect: Option<Rect>) {
+        self.scissor = rect;
+        self.update_clip_window();
+    }
+
+    pub fn scissor(&self) -> Option<Rect> {
+        self.scissor
+    }
+
+    fn update_clip_window(&mut self) {
+        let mut clip = self
+            .viewport
+            .intersection(&Rect::from_size(self.window_size));
+        if let Some(scissor) = self.scissor {
+            clip = clip.intersection(&scissor);
+        }
+        if let Some(damage) = self.damage {
+            clip = clip.intersection(&damage);
+        }
+        self.set_clip_window(ClipWindow {
+            clip_window_left_pixel_coordinate: clip.x,
+            clip_window_bottom_pixel_coordinate: clip.y,
+            clip_window_width_in_pixels: clip.width,
+            clip_window_height_in_pixels: clip.height,
+        });
+    }
+
     pub fn vp_x_scale(&self) -> f32 {
-        (self.window_size.0 * 16 / 2) as f32
+        self.clipper_xy_scaling
+            .0
+            .viewport_half_width_in_1_16th_of_pixel
     }
 
     pub fn vp_y_scale(&self) -> f32 {
-        (self.window_size.1 * 16 / 2) as f32
+        self.clipper_xy_scaling
+            .0
+            .viewport_half_height_in_1_16th_of_pixel
     }
 
     pub fn vp_z_scale(&self) -> f32 {
@@ -329,16 +705,18 @@ impl CommandEncoder {
         self.bo_buffer_map.clear();
         self.bo_handle_map.clear();
         self.bo_handles.clear();
+        self.damage = None;
 
         expand_commands!(command_recorder_clear, self);
         self.dirty_bits = 0;
     }
 
     pub fn begin_pass(&mut self) {
-        let tile_bin_config = TileBinningModeConfiguration::with_size_in_pixels(
-            self.window_size.0,
-            self.window_size.1,
-        );
+        let tile_bin_config = TileBinningModeConfiguration {
+            multisample_mode_4x: self.msaa,
+            ..Default::default()
+        }
+        .set_size_in_pixels(self.window_size.0, self.window_size.1);
         tile_bin_config.encode(&mut self.bin_cl_buf).unwrap();
         self.width_in_tiles = tile_bin_config.width_in_tiles;
         self.height_in_tiles = tile_bin_config.height_in_tiles;
@@ -351,22 +729,8 @@ impl CommandEncoder {
 
         self.set_line_width(LineWidth { line_width: 0.0 });
 
-        self.set_clip_window(ClipWindow {
-            clip_window_left_pixel_coordinate: 0,
-            clip_window_bottom_pixel_coordinate: 0,
-            clip_window_width_in_pixels: self.window_size.0,
-            clip_window_height_in_pixels: self.window_size.1,
-        });
-
-        self.set_clipper_xy_scaling(ClipperXYScaling {
-            viewport_half_width_in_1_16th_of_pixel: (self.window_size.0 * 16 / 2) as f32,
-            viewport_half_height_in_1_16th_of_pixel: (self.window_size.1 * 16 / 2) as f32,
-        });
-
-        self.set_viewport_offset(ViewportOffset {
-            viewport_centre_x_coordinate_12_4: self.window_size.0 * 16 / 2,
-            viewport_centre_y_coordinate_12_4: self.window_size.1 * 16 / 2,
-        });
+        self.scissor = None;
+        self.set_viewport(0, 0, self.window_size.0, self.window_size.1, 0.0, 1.0);
 
         let depth_test_enable = false;
         let depth_write_enable = false;
@@ -384,7 +748,7 @@ impl CommandEncoder {
             coverage_update_mode: 0,
             coverage_read_type: false,
             antialiased_points_and_lines: false,
-            rasteriser_oversample_mode: 0,
+            rasteriser_oversample_mode: if self.msaa { 1 } else { 0 },
             enable_depth_offset: false,
             clockwise_primitives: true,
             enable_reverse_facing_primitive: true,
@@ -396,11 +760,6 @@ impl CommandEncoder {
             depth_offset_units: 0.0,
         });
 
-        self.set_clipper_z_scale_and_offset(ClipperZScaleAndOffset {
-            viewport_z_scale_zc_to_zs: 0.5,
-            viewport_z_offset_zc_to_zs: 0.5,
-        });
-
         self.set_point_size(PointSize { point_size: 1.0 });
 
         self.set_flat_shade_flags(FlatShadeFlags {
@@ -420,7 +779,7 @@ impl CommandEncoder {
 
     fn add_uniform_relocs(&mut self, uniforms: &[ShaderUniform]) {
         for uniform in uniforms {
-            if let ShaderUniform::Texture(tex) = uniform {
+            if let ShaderUniform::Texture(tex) | ShaderUniform::TextureLod(tex) = uniform {
                 let tex_idx = self.relocate_buffer(tex.buffer.clone());
                 self.uniforms.push(tex_idx);
             }
@@ -431,14 +790,23 @@ impl CommandEncoder {
         for uniform in uniforms {
             match uniform {
                 ShaderUniform::Texture(tex) => {
-                    self.uniforms.push(tex.config.get_1d_word());
-                    if tex.config.height > 1 {
-                        self.uniforms.push(tex.config.get_2d_word());
-                    }
+                    tex.config.write_words(false, &mut self.uniforms);
+                }
+                ShaderUniform::TextureLod(tex) => {
+                    tex.config.write_words(true, &mut self.uniforms);
                 }
                 ShaderUniform::Constant(constant) => {
                     self.uniforms.push(*constant);
                 }
+                ShaderUniform::Stencil(stencil) => {
+                    self.uniforms.push(stencil.get_front_word());
+                    if stencil.is_two_sided() {
+                        self.uniforms.push(stencil.get_back_word());
+                    }
+                    if stencil.needs_write_mask_word() {
+                        self.uniforms.push(stencil.get_write_mask_word());
+                    }
+                }
             }
         }
     }
@@ -460,6 +828,10 @@ impl CommandEncoder {
         self.set_configuration_bits(new_configuration_bits);
     }
 
+    fn configuration_bits(&self) -> ConfigurationBits {
+        self.configuration_bits.0
+    }
+
     pub fn set_cull_test(
         &mut self,
         enable_forward_facing_primitive: bool,
@@ -482,6 +854,34 @@ impl CommandEncoder {
         fs_uniforms: &[ShaderUniform],
         vs_uniforms: &[ShaderUniform],
         cs_uniforms: &[ShaderUniform],
+    ) {
+        self.bind_shader_with_extra_uniforms(
+            fs_single_threaded,
+            fs_number_of_varyings,
+            fs,
+            vs,
+            cs,
+            attributes,
+            fs_uniforms,
+            &[],
+            vs_uniforms,
+            cs_uniforms,
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn bind_shader_with_extra_uniforms(
+        &mut self,
+        fs_single_threaded: bool,
+        fs_number_of_varyings: u8,
+        fs: buffer::Handle,
+        vs: buffer::Handle,
+        cs: buffer::Handle,
+        attributes: &[ShaderAttribute],
+        fs_uniforms: &[ShaderUniform],
+        fs_extra_uniforms: &[ShaderUniform],
+        vs_uniforms: &[ShaderUniform],
+        cs_uniforms: &[ShaderUniform],
     ) {
         GlShaderState {
             address: 0,
@@ -556,6 +956,7 @@ impl CommandEncoder {
         self.add_uniform_relocs(cs_uniforms);
 
         self.add_uniforms(fs_uniforms);
+        self.add_uniforms(fs_extra_uniforms);
         self.add_uniforms(vs_uniforms);
         self.add_uniforms(cs_uniforms);
     }
@@ -619,14 +1020,135 @@ impl CommandEncoder {
 
     pub async fn submit(
         &mut self,
-        clear_color: u32,
-        clear_z: u32,
+        color_load: LoadOp,
+        zs_load: LoadOp,
         color_write: &Buffer,
         zs_write: &Buffer,
     ) {
-        use vc4_drm::card::drm_vc4_submit_rcl_surface;
         let fb_bo_idx = self.relocate_buffer(color_write.clone());
         let zs_idx = self.relocate_buffer(zs_write.clone());
+        self.submit_surfaces(
+            color_load,
+            zs_load,
+            drm_vc4_submit_rcl_surface::new_tiled_rgba8_color_read(fb_bo_idx),
+            drm_vc4_submit_rcl_surface::new_tiled_rgba8_color_write(fb_bo_idx),
+            drm_vc4_submit_rcl_surface::new_tiled_zs(zs_idx),
+        )
+        .await;
+    }
+
+    /// Submits the pass into an offscreen `RenderTarget`. The encoder's
+    /// window size must match the target's size.
+    pub async fn submit_to_render_target(
+        &mut self,
+        color_load: LoadOp,
+        zs_load: LoadOp,
+        render_target: &RenderTarget,
+    ) {
+        debug_assert_eq!(self.window_size, render_target.size);
+        let tiling = render_target.color_tiling();
+        let color_idx = self.relocate_buffer(render_target.color.buffer.clone());
+        let zs = if let Some(depth) = &render_target.depth {
+            let zs_idx = self.relocate_buffer(depth.clone());
+            drm_vc4_submit_rcl_surface::new_tiled_zs(zs_idx).tiling(tiling)
+        } else {
+            debug_assert!(zs_load != LoadOp::Load, "render target has no depth");
+            drm_vc4_submit_rcl_surface::default()
+        };
+        self.submit_surfaces(
+            color_load,
+            zs_load,
+            drm_vc4_submit_rcl_surface::new_tiled_rgba8_color_read(color_idx).tiling(tiling),
+            drm_vc4_submit_rcl_surface::new_tiled_rgba8_color_write(color_idx)
+                .color_write_tiling(tiling),
+            zs,
+        )
+        .await;
+    }
+
+    /// `zs` is used for both reading and writing; the read and write bits
+    /// share the same layout for the ZS buffer.
+    async fn submit_surfaces(
+        &mut self,
+        color_load: LoadOp,
+        zs_load: LoadOp,
+        color_read: drm_vc4_submit_rcl_surface,
+        color_write: drm_vc4_submit_rcl_surface,
+        zs: drm_vc4_submit_rcl_surface,
+    ) {
+        // Damage that covers nothing leaves the surface as it is.
+        if self.damage.is_some_and(|damage| damage.is_empty()) {
+            return;
+        }
+
+        // Tiles overlapping the damage are rendered in full, so the pixels
+        // outside of it have to come from the previous contents.
+        let color_load = if self.damage.is_some() {
+            LoadOp::Load
+        } else {
+            color_load
+        };
+
+        // Multisampled tile buffers can only be loaded from full-resolution
+        // surfaces, which aren't kept.
+        debug_assert!(
+            !self.msaa || (color_load != LoadOp::Load && zs_load != LoadOp::Load),
+            "LoadOp::Load is not supported with MSAA"
+        );
+
+        let (color_write, zs_write) = if self.msaa {
+            (
+                color_write
+                    .color_write_ms_mode_4x(true)
+                    .color_write_decimate_mode(VC4DecimateMode::_4x),
+                drm_vc4_submit_rcl_surface::default(),
+            )
+        } else {
+            (color_write, zs)
+        };
+        let color_read = if color_load == LoadOp::Load {
+            color_read
+        } else {
+            drm_vc4_submit_rcl_surface::default()
+        };
+        let zs_read = if zs_load == LoadOp::Load {
+            zs
+        } else {
+            drm_vc4_submit_rcl_surface::default()
+        };
+        let clear_color = if let LoadOp::Clear(clear_color) = color_load {
+            clear_color
+        } else {
+            0
+        };
+        let clear_z = if let LoadOp::Clear(clear_z) = zs_load {
+            clear_z
+        } else {
+            0
+        };
+        let use_clear_color =
+            matches!(color_load, LoadOp::Clear(_)) || matches!(zs_load, LoadOp::Clear(_));
+
+        let (min_tile, max_tile) = match self.damage {
+            Some(damage) => {
+                let tile_size = self.tile_size_in_pixels();
+                (
+                    (damage.x / tile_size, damage.y / tile_size),
+                    (
+                        (damage.right() - 1) / tile_size,
+                        (damage.bottom() - 1) / tile_size,
+                    ),
+                )
+            }
+            _ => (
+                (0, 0),
+                (
+                    self.width_in_tiles as u16 - 1,
+                    self.height_in_tiles as u16 - 1,
+                ),
+            ),
+        };
+
         get_card()
             .vc4_submit_cl_async(SubmitClArgs {
                 bin_cl: &self.bin_cl_buf,
@@ -636,20 +1158,20 @@ impl CommandEncoder {
                 shader_rec_count: self.shader_rec_count,
                 width: self.window_size.0,
                 height: self.window_size.1,
-                min_x_tile: 0,
-                min_y_tile: 0,
-                max_x_tile: self.width_in_tiles - 1,
-                max_y_tile: self.height_in_tiles - 1,
-                color_read: drm_vc4_submit_rcl_surface::default(),
-                color_write: drm_vc4_submit_rcl_surface::new_tiled_rgba8_color_write(fb_bo_idx),
-                zs_read: drm_vc4_submit_rcl_surface::default(),
-                zs_write: drm_vc4_submit_rcl_surface::new_tiled_zs(zs_idx),
+                min_x_tile: min_tile.0 as u8,
+                min_y_tile: min_tile.1 as u8,
+                max_x_tile: max_tile.0 as u8,
+                max_y_tile: max_tile.1 as u8,
+                color_read,
+                color_write,
+                zs_read,
+                zs_write,
                 msaa_color_write: drm_vc4_submit_rcl_surface::default(),
                 msaa_zs_write: drm_vc4_submit_rcl_surface::default(),
                 clear_color: [clear_color, clear_color],
                 clear_z,
                 clear_s: 0,
-                use_clear_color: true,
+                use_clear_color,
                 fixed_rcl_order: false,
                 rcl_order_increasing_x: false,
                 rcl_order_increasing_y: false,
@@ -3,7 +3,7 @@ mod shaders;
 use shaders::test_model;
 
 use num_traits::float::FloatConst;
-use rpi_drm::CommandEncoder;
+use rpi_drm::{CommandEncoder, LoadOp};
 use std::io::{Read, Seek, SeekFrom};
 use vc4_drm::glam::*;
 
@@ -89,8 +89,8 @@ async fn async_main() {
         let render_start = Instant::now();
         command_encoder
             .submit(
-                clear_color,
-                clear_z,
+                LoadOp::Clear(clear_color),
+                LoadOp::Clear(clear_z),
                 &framebuffer.bo,
                 &display_framebuffers.z_buffer,
             )
@@ -0,0 +1,406 @@
+use crate::{BufferView, CommandEncoder, ShaderAttribute, ShaderUniform};
+use std::fmt;
+use vc4_drm::cl::*;
+use vc4_drm::drm::buffer;
+
+/// One attribute array of the vertex layout. `buffer_index` selects the
+/// vertex buffer passed to `CommandEncoder::bind_pipeline`, and
+/// `record.address` is an offset into that buffer view.
+#[derive(Default, Debug, Copy, Clone)]
+pub struct VertexAttribute {
+    pub buffer_index: usize,
+    pub record: AttributeRecord,
+    pub vs: bool,
+    pub cs: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DepthState {
+    pub compare_function: CompareFunction,
+    pub write: bool,
+}
+
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub enum CullMode {
+    #[default]
+    None,
+    Front,
+    Back,
+}
+
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub enum FrontFace {
+    #[default]
+    Clockwise,
+    CounterClockwise,
+}
+
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub enum BlendFactor {
+    Zero,
+    #[default]
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstColor,
+    OneMinusDstColor,
+    DstAlpha,
+    OneMinusDstAlpha,
+    SrcAlphaSaturate,
+    ConstantColor,
+    OneMinusConstantColor,
+    ConstantAlpha,
+    OneMinusConstantAlpha,
+}
+
+impl BlendFactor {
+    fn uses_constant_color(self) -> bool {
+        matches!(
+            self,
+            BlendFactor::ConstantColor | BlendFactor::OneMinusConstantColor
+        )
+    }
+
+    fn uses_constant_alpha(self) -> bool {
+        matches!(
+            self,
+            BlendFactor::ConstantAlpha | BlendFactor::OneMinusConstantAlpha
+        )
+    }
+}
+
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub enum BlendOp {
+    #[default]
+    Add,
+    Subtract,
+    ReverseSubtract,
+    Min,
+    Max,
+}
+
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub struct BlendComponent {
+    pub src_factor: BlendFactor,
+    pub dst_factor: BlendFactor,
+    pub op: BlendOp,
+}
+
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub struct BlendState {
+    pub color: BlendComponent,
+    pub alpha: BlendComponent,
+    /// RGBA blend constant, only read by the `Constant*` factors.
+    pub constant: [f32; 4],
+}
+
+impl BlendState {
+    pub const ALPHA_BLENDING: BlendState = BlendState {
+        color: BlendComponent {
+            src_factor: BlendFactor::SrcAlpha,
+            dst_factor: BlendFactor::OneMinusSrcAlpha,
+            op: BlendOp::Add,
+        },
+        alpha: BlendComponent {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::OneMinusSrcAlpha,
+            op: BlendOp::Add,
+        },
+        constant: [0.0; 4],
+    };
+
+    fn components(&self) -> [&BlendComponent; 2] {
+        [&self.color, &self.alpha]
+    }
+
+    fn uses_constant_color(&self) -> bool {
+        self.components()
+            .iter()
+            .any(|c| c.src_factor.uses_constant_color() || c.dst_factor.uses_constant_color())
+    }
+
+    fn uses_constant_alpha(&self) -> bool {
+        self.components()
+            .iter()
+            .any(|c| c.src_factor.uses_constant_alpha() || c.dst_factor.uses_constant_alpha())
+    }
+
+    /// Blend constant words the fragment shader loads: the color packed in
+    /// the byte order of the BGRA color buffer if a `Constant*Color` factor
+    /// is used, then the alpha in every byte if a `Constant*Alpha` one is.
+    pub fn constant_uniforms(&self) -> Vec<u32> {
+        let mut uniforms = Vec::new();
+        let to_u8 = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u32;
+        let [r, g, b, a] = self.constant.map(to_u8);
+        if self.uses_constant_color() {
+            uniforms.push(b | (g << 8) | (r << 16) | (a << 24));
+        }
+        if self.uses_constant_alpha() {
+            uniforms.push(a * 0x01010101);
+        }
+        uniforms
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ColorMask {
+    pub r: bool,
+    pub g: bool,
+    pub b: bool,
+    pub a: bool,
+}
+
+impl ColorMask {
+    pub const ALL: ColorMask = ColorMask {
+        r: true,
+        g: true,
+        b: true,
+        a: true,
+    };
+    pub const NONE: ColorMask = ColorMask {
+        r: false,
+        g: false,
+        b: false,
+        a: false,
+    };
+}
+
+impl Default for ColorMask {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// Fixed-function state of a draw, validated once by `build`.
+///
+/// VC4 has no blend or color mask hardware; the fragment shader reads the
+/// tile buffer and does both itself. The fragment shader must therefore be
+/// compiled for the same `blend`, `color_mask` and `stencil` state. The blend
+/// constant and stencil setup words it loads are appended to the fragment
+/// shader uniforms when the pipeline is bound, in that order.
+#[derive(Debug, Clone)]
+pub struct PipelineState {
+    pub fs: buffer::Handle,
+    pub vs: buffer::Handle,
+    pub cs: buffer::Handle,
+    pub fs_single_threaded: bool,
+    pub fs_number_of_varyings: u8,
+    pub attributes: Vec<VertexAttribute>,
+    pub depth: Option<DepthState>,
+    pub cull_mode: CullMode,
+    pub front_face: FrontFace,
+    pub depth_offset: Option<DepthOffset>,
+    pub blend: Option<BlendState>,
+    pub color_mask: ColorMask,
+    pub stencil: Option<StencilConfigUniform>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PipelineError {
+    TooManyAttributes(usize),
+    /// Attribute read by neither the vertex nor the coordinate shader.
+    UnusedAttribute(usize),
+    DepthOffsetWithoutDepthTest,
+    /// Min and max ignore the blend factors, which must be `One`.
+    MinMaxBlendFactor,
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::TooManyAttributes(count) => {
+                write!(f, "{count} vertex attributes, at most 8 are supported")
+            }
+            PipelineError::UnusedAttribute(index) => {
+                write!(f, "vertex attribute {index} isn't read by any shader")
+            }
+            PipelineError::DepthOffsetWithoutDepthTest => {
+                write!(f, "depth offset requires a depth test")
+            }
+            PipelineError::MinMaxBlendFactor => {
+                write!(f, "min and max blend ops require factors of One")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+/// Validated `PipelineState`, bound with `CommandEncoder::bind_pipeline`.
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    state: PipelineState,
+    configuration_bits: ConfigurationBits,
+    blend_constant_uniforms: Vec<u32>,
+}
+
+impl PipelineState {
+    pub fn new(fs: buffer::Handle, vs: buffer::Handle, cs: buffer::Handle) -> Self {
+        Self {
+            fs,
+            vs,
+            cs,
+            fs_single_threaded: false,
+            fs_number_of_varyings: 0,
+            attributes: Vec::new(),
+            depth: None,
+            cull_mode: CullMode::None,
+            front_face: FrontFace::Clockwise,
+            depth_offset: None,
+            blend: None,
+            color_mask: ColorMask::ALL,
+            stencil: None,
+        }
+    }
+
+    pub fn build(self) -> Result<Pipeline, PipelineError> {
+        if self.attributes.len() > 8 {
+            return Err(PipelineError::TooManyAttributes(self.attributes.len()));
+        }
+        for (i, attribute) in self.attributes.iter().enumerate() {
+            if !attribute.vs && !attribute.cs {
+                return Err(PipelineError::UnusedAttribute(i));
+            }
+        }
+        if self.depth_offset.is_some() && self.depth.is_none() {
+            return Err(PipelineError::DepthOffsetWithoutDepthTest);
+        }
+        if let Some(blend) = &self.blend {
+            for component in blend.components() {
+                if matches!(component.op, BlendOp::Min | BlendOp::Max)
+                    && (component.src_factor != BlendFactor::One
+                        || component.dst_factor != BlendFactor::One)
+                {
+                    return Err(PipelineError::MinMaxBlendFactor);
+                }
+            }
+        }
+
+        let configuration_bits = self.configuration_bits();
+        let blend_constant_uniforms = self.blend_constant_uniforms();
+        Ok(Pipeline {
+            state: self,
+            configuration_bits,
+            blend_constant_uniforms,
+        })
+    }
+
+    fn configuration_bits(&self) -> ConfigurationBits {
+        let (depth_test_function, z_updates_enable) = match self.depth {
+            Some(depth) => (depth.compare_function, depth.write),
+            None => (CompareFunction::Always, false),
+        };
+
+        // Early Z can only reject fragments when the later depth test would
+        // as well, and when nothing needs to run for the rejected fragments.
+        let stencil_needs_zfail = self.stencil.is_some_and(|stencil| {
+            stencil.front.depth_fail_op != StencilOp::Keep
+                || stencil.back.depth_fail_op != StencilOp::Keep
+        });
+        let early_z_enable = matches!(
+            depth_test_function,
+            CompareFunction::Less | CompareFunction::LEqual
+        ) && !stencil_needs_zfail;
+
+        ConfigurationBits {
+            enable_forward_facing_primitive: self.cull_mode != CullMode::Front,
+            enable_reverse_facing_primitive: self.cull_mode != CullMode::Back,
+            clockwise_primitives: self.front_face == FrontFace::Clockwise,
+            enable_depth_offset: self.depth_offset.is_some(),
+            depth_test_function,
+            z_updates_enable,
+            early_z_enable,
+            early_z_updates_enable: true,
+            ..Default::default()
+        }
+    }
+
+    fn blend_constant_uniforms(&self) -> Vec<u32> {
+        let mut uniforms = Vec::new();
+
+        if let Some(blend) = &self.blend {
+            uniforms.extend(blend.constant_uniforms());
+        }
+
+        uniforms
+    }
+}
+
+impl Pipeline {
+    pub fn state(&self) -> &PipelineState {
+        &self.state
+    }
+}
+
+impl CommandEncoder {
+    /// Binds the shaders and fixed-function state of `pipeline`.
+    /// `vertex_buffers` are indexed by `VertexAttribute::buffer_index`, and
+    /// must hold every buffer the attributes refer to.
+    pub fn bind_pipeline(
+        &mut self,
+        pipeline: &Pipeline,
+        vertex_buffers: &[&BufferView],
+        fs_uniforms: &[ShaderUniform],
+        vs_uniforms: &[ShaderUniform],
+        cs_uniforms: &[ShaderUniform],
+    ) {
+        let state = &pipeline.state;
+        for (i, attribute) in state.attributes.iter().enumerate() {
+            assert!(
+                attribute.buffer_index < vertex_buffers.len(),
+                "vertex attribute {i} reads vertex buffer {}, but only {} are bound",
+                attribute.buffer_index,
+                vertex_buffers.len()
+            );
+        }
+
+        let mut configuration_bits = pipeline.configuration_bits;
+        // Multisampling belongs to the pass, not the pipeline.
+        configuration_bits.rasteriser_oversample_mode =
+            self.configuration_bits().rasteriser_oversample_mode;
+        self.set_configuration_bits(configuration_bits);
+        if let Some(depth_offset) = state.depth_offset {
+            self.set_depth_offset(depth_offset);
+        }
+
+        let attributes: Vec<ShaderAttribute> = state
+            .attributes
+            .iter()
+            .map(|attribute| {
+                let view = vertex_buffers[attribute.buffer_index];
+                let mut record = attribute.record;
+                record.address += view.range.start;
+                ShaderAttribute {
+                    buffer: &view.buffer,
+                    record,
+                    vs: attribute.vs,
+                    cs: attribute.cs,
+                }
+            })
+            .collect();
+
+        let mut fs_extra_uniforms: Vec<ShaderUniform> = pipeline
+            .blend_constant_uniforms
+            .iter()
+            .map(|constant| ShaderUniform::Constant(*constant))
+            .collect();
+        if let Some(stencil) = &state.stencil {
+            fs_extra_uniforms.push(ShaderUniform::Stencil(stencil));
+        }
+
+        self.bind_shader_with_extra_uniforms(
+            state.fs_single_threaded,
+            state.fs_number_of_varyings,
+            state.fs,
+            state.vs,
+            state.cs,
+            &attributes,
+            fs_uniforms,
+            &fs_extra_uniforms,
+            vs_uniforms,
+            cs_uniforms,
+        );
+    }
+}
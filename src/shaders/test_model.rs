@@ -3,10 +3,7 @@ use rpi_drm::{Buffer, BufferView, CommandEncoder, TextureUniform};
 use std::fs;
 use std::io::{BufReader, Read, Seek};
 use std::sync::OnceLock;
-use vc4_drm::cl::{
-    CompareFunction, IndexType, PrimitiveMode, TextureConfigUniform, TextureDataType,
-    TextureMinFilterType,
-};
+use vc4_drm::cl::{CompareFunction, IndexType, PrimitiveMode};
 use vc4_drm::glam::{Mat3, Mat4, UVec2};
 
 pub struct Model {
@@ -60,48 +57,7 @@ pub fn get_model() -> &'static Model {
 pub fn get_texture() -> &'static TextureUniform {
     static TEX: OnceLock<TextureUniform> = OnceLock::new();
     TEX.get_or_init(|| {
-        use flate2::read::ZlibDecoder;
-        use vc4_drm::vc4_image_addr::{Translator, TranslatorTrait};
-
-        let mut ctx_f = fs::File::open("resources/generated/citrus_normals.ctx").unwrap();
-
-        let mut header_data = [0_u8; 16];
-        ctx_f.read(&mut header_data).unwrap();
-        assert_eq!(
-            u32::from_le_bytes(header_data[0..4].try_into().unwrap()),
-            0x005072C2_u32
-        );
-        let total_size = u32::from_le_bytes(header_data[4..8].try_into().unwrap());
-        let num_mips = u16::from_le_bytes(header_data[8..10].try_into().unwrap());
-        let mip0_page_offset = u16::from_le_bytes(header_data[10..12].try_into().unwrap());
-        let width = u16::from_le_bytes(header_data[12..14].try_into().unwrap());
-        let height = u16::from_le_bytes(header_data[14..16].try_into().unwrap());
-
-        let bo = Buffer::new(total_size);
-        {
-            let mut mapping = bo.mmap();
-            let mut d = ZlibDecoder::new(ctx_f);
-            d.read_exact(mapping.as_mut()).unwrap();
-        }
-
-        TextureUniform {
-            buffer: bo,
-            config: TextureConfigUniform {
-                base_address: mip0_page_offset as _,
-                cache_swizzle: 0,
-                cube_map: false,
-                flip_y: false,
-                data_type: TextureDataType::RGBA8888,
-                num_mips: num_mips as _,
-                height,
-                etc_flip: false,
-                width,
-                mag_filt: Default::default(),
-                min_filt: TextureMinFilterType::LinearMipLinear,
-                wrap_t: Default::default(),
-                wrap_s: Default::default(),
-            },
-        }
+        TextureUniform::from_ctx_file("resources/generated/citrus_normals.ctx").unwrap()
     })
 }
 
@@ -0,0 +1,199 @@
+use crate::{Buffer, TextureUniform};
+use vc4_drm::cl::*;
+use vc4_drm::glam::UVec2;
+use vc4_drm::vc4_image_addr::{MipChainLayout, Translator};
+
+#[derive(Debug, Copy, Clone)]
+pub struct TextureOptions {
+    /// Box filters a full mip chain down to 1x1. Levels below an NPOT base
+    /// have the power-of-two sizes the TMU expects, so level 1 of a 100x60
+    /// texture is 64x32, each scaled down from the whole level above. Without
+    /// mips, `min_filt` should be `Linear` or `Nearest`.
+    pub generate_mips: bool,
+    pub mag_filt: TextureMagFilterType,
+    pub min_filt: TextureMinFilterType,
+    /// NPOT sizes can't repeat, use `Clamp` or `Mirror` for those.
+    pub wrap_s: TextureWrapType,
+    pub wrap_t: TextureWrapType,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            generate_mips: true,
+            mag_filt: TextureMagFilterType::Linear,
+            min_filt: TextureMinFilterType::LinearMipLinear,
+            wrap_s: TextureWrapType::Repeat,
+            wrap_t: TextureWrapType::Repeat,
+        }
+    }
+}
+
+/// Texture created at runtime, laid out exactly like the ones packed by
+/// `vc4-pack-textures`.
+pub struct Texture {
+    pub uniform: TextureUniform,
+}
+
+impl Texture {
+    /// Uploads top-down rows of 8-bit RGBA pixels into a new BO.
+    pub fn from_rgba8(width: u16, height: u16, data: &[u8], options: &TextureOptions) -> Self {
+        assert!(width > 0 && height > 0 && width <= 2048 && height <= 2048);
+        assert_eq!(data.len(), width as usize * height as usize * 4);
+
+        let size = UVec2::new(width as u32, height as u32);
+        let num_mips = if options.generate_mips {
+            u32::min(size.x.ilog2(), size.y.ilog2()) + 1
+        } else {
+            1
+        };
+        let layout = MipChainLayout::new(size, 32, num_mips);
+
+        let buffer = Buffer::new(layout.total_size);
+        {
+            let mut mapping = buffer.mmap();
+            let mapping = mapping.as_mut();
+
+            // Levels are kept linear and bottom-up while filtering, matching
+            // the orientation the TMU expects.
+            let mut pixels: Vec<[u8; 4]> = data
+                .chunks_exact(width as usize * 4)
+                .rev()
+                .flat_map(|row| row.chunks_exact(4).map(|p| [p[0], p[1], p[2], p[3]]))
+                .collect();
+            let mut pixels_size = size;
+
+            for (level, mip) in layout.levels.iter().enumerate() {
+                if level > 0 {
+                    pixels = downsample_rgba8(&pixels, pixels_size, mip.size);
+                    pixels_size = mip.size;
+                }
+
+                let bgra: Vec<u8> = pixels
+                    .iter()
+                    .flat_map(|p| [p[2], p[1], p[0], p[3]])
+                    .collect();
+                let level_slice = &mut mapping[mip.offset as usize..];
+                Translator::new(mip.size, 32).tile(&bgra, mip.size.x as usize * 4, level_slice);
+            }
+        }
+
+        Self {
+            uniform: TextureUniform {
+                buffer,
+                config: TextureConfigUniform {
+                    base_address: layout.base_page(),
+                    cache_swizzle: 0,
+                    cube_map: false,
+                    flip_y: false,
+                    data_type: TextureDataType::RGBA8888,
+                    num_mips: num_mips as _,
+                    height,
+                    etc_flip: false,
+                    width,
+                    mag_filt: options.mag_filt,
+                    min_filt: options.min_filt,
+                    wrap_t: options.wrap_t,
+                    wrap_s: options.wrap_s,
+                    cube_map_stride: 0,
+                    border_color: 0,
+                    child_image: None,
+                },
+            },
+        }
+    }
+
+    /// Overwrites the `width` x `height` rectangle at `x`, `y` of the base
+    /// level with top-down rows of texels in the texture's own format, BGRA
+    /// for `RGBA8888`. Meant for textures updated in small pieces, such as
+    /// glyph caches: lower mips are left as they are, and only the utiles
+    /// the rectangle touches are written.
+    ///
+    /// Panics for ETC1, raster and cube map textures.
+    pub fn update_region(&mut self, x: u16, y: u16, width: u16, height: u16, data: &[u8]) {
+        let config = &self.uniform.config;
+        assert!(!config.cube_map, "cube maps can't be updated");
+        let bpp = tiled_bpp(config.data_type)
+            .unwrap_or_else(|| panic!("{:?} textures can't be updated", config.data_type));
+        assert!(x as u32 + width as u32 <= config.width as u32);
+        assert!(y as u32 + height as u32 <= config.height as u32);
+        let stride = (width as usize * bpp as usize).div_ceil(8);
+        assert_eq!(data.len(), stride * height as usize);
+
+        // Tiled images are stored bottom-up.
+        let bottom_up: Vec<u8> = data.chunks_exact(stride).rev().flatten().copied().collect();
+        let size = UVec2::new(config.width as u32, config.height as u32);
+        let origin = UVec2::new(x as u32, (config.height - y - height) as u32);
+        let rect_size = UVec2::new(width as u32, height as u32);
+
+        let mut mapping = self.uniform.buffer.mmap();
+        let level_slice = &mut mapping.as_mut()[config.base_address as usize * 4096..];
+        Translator::new(size, bpp).update_rect(&bottom_up, stride, origin, rect_size, level_slice);
+    }
+}
+
+/// Bits per texel of the data types tiled texel by texel, `None` for ETC1
+/// and the raster types.
+pub(crate) fn tiled_bpp(data_type: TextureDataType) -> Option<u32> {
+    Some(match data_type {
+        TextureDataType::RGBA64 => 64,
+        TextureDataType::RGBA8888 | TextureDataType::RGBX8888 => 32,
+        TextureDataType::RGBA4444
+        | TextureDataType::RGBA5551
+        | TextureDataType::RGB565
+        | TextureDataType::LumAlpha
+        | TextureDataType::S16F
+        | TextureDataType::S16 => 16,
+        TextureDataType::Luminance | TextureDataType::Alpha | TextureDataType::S8 => 8,
+        TextureDataType::A4 => 4,
+        TextureDataType::BW1 | TextureDataType::A1 => 1,
+        TextureDataType::ETC1 | TextureDataType::RGBA32R | TextureDataType::YUYV422R => {
+            return None
+        }
+    })
+}
+
+/// Box filters rows of 8-bit RGBA pixels down to `dst_size`, weighting
+/// each source pixel by how much of it a destination pixel covers. NPOT
+/// sources are scaled as a whole into the smaller power-of-two size, like
+/// the box filter of `vc4-pack-textures` does.
+pub fn downsample_rgba8(src: &[[u8; 4]], src_size: UVec2, dst_size: UVec2) -> Vec<[u8; 4]> {
+    assert_eq!(src.len(), (src_size.x * src_size.y) as usize);
+    let x_taps = box_taps(src_size.x, dst_size.x);
+    let y_taps = box_taps(src_size.y, dst_size.y);
+
+    let mut dst = Vec::with_capacity((dst_size.x * dst_size.y) as usize);
+    for y_taps in &y_taps {
+        for x_taps in &x_taps {
+            let mut sum_pixel = [0_f32; 4];
+            for &(sy, wy) in y_taps {
+                for &(sx, wx) in x_taps {
+                    let p = src[(sy * src_size.x + sx) as usize];
+                    for i in 0..4 {
+                        sum_pixel[i] += p[i] as f32 * wx * wy;
+                    }
+                }
+            }
+            dst.push(sum_pixel.map(|c| (c + 0.5) as u8));
+        }
+    }
+    dst
+}
+
+/// Source pixels under each destination pixel of a row or column, with the
+/// fraction of the destination pixel they cover.
+fn box_taps(src_len: u32, dst_len: u32) -> Vec<Vec<(u32, f32)>> {
+    let scale = src_len as f32 / dst_len as f32;
+    (0..dst_len)
+        .map(|d| {
+            let (start, end) = (d as f32 * scale, (d + 1) as f32 * scale);
+            (start.floor() as u32..u32::min(end.ceil() as u32, src_len))
+                .map(|s| {
+                    let overlap = f32::min(end, s as f32 + 1.0) - f32::max(start, s as f32);
+                    (s, overlap / scale)
+                })
+                .filter(|&(_, w)| w > 0.0)
+                .collect()
+        })
+        .collect()
+}
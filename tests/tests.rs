@@ -0,0 +1,132 @@
+use rpi_drm::{
+    downsample_rgba8, BlendComponent, BlendFactor, BlendOp, BlendState, CommandEncoder,
+    PipelineError, PipelineState, Rect,
+};
+use std::num::NonZeroU32;
+use vc4_drm::drm::buffer;
+use vc4_drm::glam::UVec2;
+
+#[test]
+fn rect_edges_saturate() {
+    let rect = Rect::new(65000, 65500, 1000, 100);
+    assert_eq!(rect.right(), u16::MAX);
+    assert_eq!(rect.bottom(), u16::MAX);
+    assert_eq!(Rect::new(10, 20, 30, 40).right(), 40);
+    assert_eq!(Rect::new(10, 20, 30, 40).bottom(), 60);
+}
+
+#[test]
+fn damage_is_clipped_to_window() {
+    let mut encoder = CommandEncoder::new((640, 480));
+    encoder.set_damage(&[Rect::new(600, 10, 100, 20), Rect::new(10, 400, 5, 200)]);
+    assert_eq!(encoder.damage(), Some(Rect::new(10, 10, 630, 470)));
+
+    encoder.set_damage(&[]);
+    assert_eq!(encoder.damage(), None);
+}
+
+#[test]
+fn damage_outside_window_is_empty() {
+    let mut encoder = CommandEncoder::new((640, 480));
+    encoder.set_damage(&[Rect::new(700, 10, 100, 20), Rect::new(10, 500, 5, 5)]);
+    assert!(encoder.damage().is_some_and(|damage| damage.is_empty()));
+
+    encoder.set_damage(&[Rect::new(10, 10, 0, 20), Rect::default()]);
+    assert!(encoder.damage().is_some_and(|damage| damage.is_empty()));
+
+    // Edges past u16::MAX saturate rather than wrap into the window.
+    encoder.set_damage(&[Rect::new(65000, 65000, 1000, 1000)]);
+    assert!(encoder.damage().is_some_and(|damage| damage.is_empty()));
+}
+
+fn shader_handle(handle: u32) -> buffer::Handle {
+    buffer::Handle::from(NonZeroU32::new(handle).unwrap())
+}
+
+fn constant_blend(src_factor: BlendFactor, dst_factor: BlendFactor) -> BlendState {
+    let component = BlendComponent {
+        src_factor,
+        dst_factor,
+        op: BlendOp::Add,
+    };
+    BlendState {
+        color: component,
+        alpha: component,
+        constant: [1.0, 0.5, 0.25, 0.75],
+    }
+}
+
+#[test]
+fn blend_constant_packing() {
+    assert!(BlendState::ALPHA_BLENDING.constant_uniforms().is_empty());
+
+    // BGRA byte order, rounded to 8 bits.
+    let color = constant_blend(BlendFactor::ConstantColor, BlendFactor::Zero);
+    assert_eq!(color.constant_uniforms(), [0xbfff8040]);
+
+    let alpha = constant_blend(BlendFactor::One, BlendFactor::OneMinusConstantAlpha);
+    assert_eq!(alpha.constant_uniforms(), [0xbfbfbfbf]);
+
+    let both = constant_blend(
+        BlendFactor::ConstantAlpha,
+        BlendFactor::OneMinusConstantColor,
+    );
+    assert_eq!(both.constant_uniforms(), [0xbfff8040, 0xbfbfbfbf]);
+}
+
+#[test]
+fn blend_min_max_need_factors_of_one() {
+    let mut state = PipelineState::new(shader_handle(1), shader_handle(2), shader_handle(3));
+    state.blend = Some(BlendState {
+        color: BlendComponent {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::One,
+            op: BlendOp::Max,
+        },
+        ..BlendState::ALPHA_BLENDING
+    });
+    assert!(state.clone().build().is_ok());
+
+    state.blend = Some(BlendState {
+        alpha: BlendComponent {
+            src_factor: BlendFactor::SrcAlpha,
+            dst_factor: BlendFactor::One,
+            op: BlendOp::Min,
+        },
+        ..BlendState::ALPHA_BLENDING
+    });
+    assert_eq!(state.build().unwrap_err(), PipelineError::MinMaxBlendFactor);
+}
+
+#[test]
+fn npot_mip_covers_whole_level() {
+    // Left half black, right half white: level 1 of 100x60 is 64x32.
+    let src_size = UVec2::new(100, 60);
+    let src: Vec<[u8; 4]> = (0..src_size.x * src_size.y)
+        .map(|i| {
+            if i % src_size.x < 50 {
+                [0, 0, 0, 255]
+            } else {
+                [255; 4]
+            }
+        })
+        .collect();
+    let dst_size = UVec2::new(64, 32);
+    let dst = downsample_rgba8(&src, src_size, dst_size);
+    assert_eq!(dst.len(), 64 * 32);
+
+    for row in dst.chunks_exact(dst_size.x as usize) {
+        assert!(row[..32].iter().all(|p| *p == [0, 0, 0, 255]));
+        assert!(row[32..].iter().all(|p| *p == [255; 4]));
+    }
+    let mean = dst.iter().map(|p| p[0] as u32).sum::<u32>() / dst.len() as u32;
+    assert_eq!(mean, 127);
+}
+
+#[test]
+fn npot_mip_blends_partly_covered_pixels() {
+    // Each of the 2 destination pixels covers one and a half source pixels.
+    let src = [[0, 0, 0, 0], [100, 100, 100, 100], [200, 200, 200, 200]];
+    let dst = downsample_rgba8(&src, UVec2::new(3, 1), UVec2::new(2, 1));
+    assert_eq!(dst, [[33; 4], [167; 4]]);
+}
@@ -39,6 +39,15 @@ pub enum VC4TilingFormat {
     LT = 2,
 }
 
+#[derive(Default, Debug, Copy, Clone)]
+#[repr(u8)]
+pub enum VC4DecimateMode {
+    #[default]
+    _1x = 0,
+    _4x = 1,
+    _16x = 2,
+}
+
 mod ffi {
     #![allow(nonstandard_style)]
 
@@ -71,7 +80,7 @@ mod ffi {
         }
     }
 
-    use super::{VC4Buffer, VC4Format, VC4RenderConfigFormat, VC4TilingFormat};
+    use super::{VC4Buffer, VC4DecimateMode, VC4Format, VC4RenderConfigFormat, VC4TilingFormat};
 
     impl drm_vc4_submit_rcl_surface {
         pub fn new_tiled_rgba8_color_write(hindex: u32) -> Self {
@@ -85,6 +94,18 @@ mod ffi {
             .color_write_tiling(VC4TilingFormat::T)
         }
 
+        pub fn new_tiled_rgba8_color_read(hindex: u32) -> Self {
+            Self {
+                hindex,
+                offset: 0,
+                bits: 0,
+                flags: 0,
+            }
+            .buffer(VC4Buffer::Color)
+            .tiling(VC4TilingFormat::T)
+            .format(VC4Format::RGBA8888)
+        }
+
         pub fn new_tiled_zs(hindex: u32) -> Self {
             Self {
                 hindex,
@@ -108,6 +129,18 @@ mod ffi {
             self
         }
 
+        pub fn color_write_ms_mode_4x(mut self, ms_mode_4x: bool) -> Self {
+            self.bits &= !(0x1 << 0);
+            self.bits |= (ms_mode_4x as u16) << 0;
+            self
+        }
+
+        pub fn color_write_decimate_mode(mut self, mode: VC4DecimateMode) -> Self {
+            self.bits &= !(0x3 << 4);
+            self.bits |= (mode as u16) << 4;
+            self
+        }
+
         pub fn buffer(mut self, buffer: VC4Buffer) -> Self {
             self.bits &= !(0x7 << 0);
             self.bits |= (buffer as u16) << 0;
@@ -239,6 +239,20 @@ pub enum CompareFunction {
     Always = 7,
 }
 
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum StencilOp {
+    Zero = 0,
+    #[default]
+    Keep = 1,
+    Replace = 2,
+    Incr = 3,
+    Decr = 4,
+    Invert = 5,
+    IncrWrap = 6,
+    DecrWrap = 7,
+}
+
 #[derive(Default, Debug, Copy, Clone, PartialEq)]
 pub struct ConfigurationBits {
     pub enable_forward_facing_primitive: bool,
@@ -504,7 +518,7 @@ impl BinClStructure for GlShaderRecord {
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Copy, Clone)]
 pub struct AttributeRecord {
     pub address: u32,
     pub number_of_bytes_minus_1: u8,
@@ -579,6 +593,70 @@ pub enum TextureWrapType {
     Border = 3,
 }
 
+/// Converts raw field values, such as the ones stored in `.ctx` headers,
+/// back into the texture enums.
+macro_rules! impl_try_from_u8 {
+    ($ty:ident { $($variant:ident),* $(,)? }) => {
+        impl TryFrom<u8> for $ty {
+            type Error = u8;
+
+            fn try_from(value: u8) -> std::result::Result<Self, u8> {
+                $(
+                    if value == $ty::$variant as u8 {
+                        return Ok($ty::$variant);
+                    }
+                )*
+                Err(value)
+            }
+        }
+    };
+}
+
+impl_try_from_u8!(TextureDataType {
+    RGBA8888,
+    RGBX8888,
+    RGBA4444,
+    RGBA5551,
+    RGB565,
+    Luminance,
+    Alpha,
+    LumAlpha,
+    ETC1,
+    S16F,
+    S8,
+    S16,
+    BW1,
+    A4,
+    A1,
+    RGBA64,
+    RGBA32R,
+    YUYV422R,
+});
+impl_try_from_u8!(TextureMagFilterType { Linear, Nearest });
+impl_try_from_u8!(TextureMinFilterType {
+    Linear,
+    Nearest,
+    NearestMipNearest,
+    NearestMipLinear,
+    LinearMipNearest,
+    LinearMipLinear,
+});
+impl_try_from_u8!(TextureWrapType {
+    Repeat,
+    Clamp,
+    Mirror,
+    Border
+});
+
+/// Level used in place of the power-of-two mip chain, for NPOT images.
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub struct TextureChildImage {
+    pub width: u16,
+    pub height: u16,
+    pub x_offset: u16,
+    pub y_offset: u16,
+}
+
 #[derive(Default, Debug)]
 pub struct TextureConfigUniform {
     pub base_address: u32,
@@ -595,13 +673,21 @@ pub struct TextureConfigUniform {
     pub min_filt: TextureMinFilterType,
     pub wrap_t: TextureWrapType,
     pub wrap_s: TextureWrapType,
+
+    /// Bytes between the faces of a cube map, a multiple of 4096.
+    pub cube_map_stride: u32,
+    /// Packed like a texel of `data_type`, used by `TextureWrapType::Border`.
+    pub border_color: u32,
+    pub child_image: Option<TextureChildImage>,
 }
 
 impl TextureConfigUniform {
     pub fn get_1d_word(&self) -> u32 {
         ((self.num_mips as u32 - 1) & 0xf)
             | (((self.data_type as u32) & 0xf) << 4)
+            | ((self.flip_y as u32) << 8)
             | ((self.cube_map as u32) << 9)
+            | (((self.cache_swizzle as u32) & 0x3) << 10)
             | ((self.base_address & 0xfffff) << 12)
     }
 
@@ -611,7 +697,138 @@ impl TextureConfigUniform {
             | (((self.min_filt as u32) & 0x7) << 4)
             | (((self.mag_filt as u32) & 0x1) << 7)
             | (((self.width as u32) & 0x7ff) << 8)
+            | ((self.etc_flip as u32) << 19)
             | (((self.height as u32) & 0x7ff) << 20)
             | ((((self.data_type as u32) & 0x10) >> 4) << 31)
     }
+
+    /// `explicit_lod` makes the TMU take the R coordinate as the level of
+    /// detail rather than as a bias.
+    pub fn get_cube_map_stride_word(&self, explicit_lod: bool) -> u32 {
+        const CUBE_MAP_STRIDE: u32 = 1;
+        (explicit_lod as u32)
+            | (((self.cube_map_stride >> 12) & 0x3ffff) << 12)
+            | (CUBE_MAP_STRIDE << 30)
+    }
+
+    pub fn get_child_image_dimensions_word(&self) -> u32 {
+        const CHILD_IMAGE_DIMENSIONS: u32 = 2;
+        let child = self.child_image.unwrap_or_default();
+        ((child.width as u32) & 0x7ff)
+            | (((child.height as u32) & 0x7ff) << 12)
+            | (CHILD_IMAGE_DIMENSIONS << 30)
+    }
+
+    pub fn get_child_image_offsets_word(&self) -> u32 {
+        const CHILD_IMAGE_OFFSETS: u32 = 3;
+        let child = self.child_image.unwrap_or_default();
+        ((child.x_offset as u32) & 0x7ff)
+            | (((child.y_offset as u32) & 0x7ff) << 12)
+            | (CHILD_IMAGE_OFFSETS << 30)
+    }
+
+    pub fn uses_border_color(&self) -> bool {
+        matches!(self.wrap_s, TextureWrapType::Border)
+            || matches!(self.wrap_t, TextureWrapType::Border)
+    }
+
+    /// Appends the uniforms a texture lookup compiled by Mesa reads: the
+    /// border color if either wrap is `Border`, then P0 and P1, then P2 with
+    /// the cube map stride for cube maps and lookups with an explicit LOD, or
+    /// else the child image dimensions and offsets if there is a child image.
+    pub fn write_words(&self, explicit_lod: bool, words: &mut Vec<u32>) {
+        if self.uses_border_color() {
+            words.push(self.border_color);
+        }
+        words.push(self.get_1d_word());
+        words.push(self.get_2d_word());
+        if self.cube_map || explicit_lod {
+            words.push(self.get_cube_map_stride_word(explicit_lod));
+        } else if self.child_image.is_some() {
+            words.push(self.get_child_image_dimensions_word());
+            words.push(self.get_child_image_offsets_word());
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StencilFaceConfig {
+    pub compare_function: CompareFunction,
+    pub fail_op: StencilOp,
+    pub depth_fail_op: StencilOp,
+    pub pass_op: StencilOp,
+    pub reference: u8,
+    pub value_mask: u8,
+    pub write_mask: u8,
+}
+
+impl Default for StencilFaceConfig {
+    fn default() -> Self {
+        Self {
+            compare_function: CompareFunction::Always,
+            fail_op: StencilOp::Keep,
+            depth_fail_op: StencilOp::Keep,
+            pass_op: StencilOp::Keep,
+            reference: 0,
+            value_mask: 0xff,
+            write_mask: 0xff,
+        }
+    }
+}
+
+impl StencilFaceConfig {
+    /// Write masks the config word can hold; others need the extra
+    /// write mask word.
+    fn write_mask_code(&self) -> Option<u32> {
+        match self.write_mask {
+            0x1 => Some(0),
+            0x3 => Some(1),
+            0xf => Some(2),
+            0xff => Some(3),
+            _ => None,
+        }
+    }
+
+    fn get_word(&self, select: u32) -> u32 {
+        (self.value_mask as u32)
+            | ((self.reference as u32) << 8)
+            | (((self.compare_function as u32) & 0x7) << 16)
+            | (((self.fail_op as u32) & 0x7) << 19)
+            | (((self.pass_op as u32) & 0x7) << 22)
+            | (((self.depth_fail_op as u32) & 0x7) << 25)
+            | ((self.write_mask_code().unwrap_or(0) & 0x3) << 28)
+            | (select << 30)
+    }
+}
+
+/// Stencil setup the fragment shader writes to the TLB, in the layout of
+/// Mesa's `vc4_get_stencil_config`. The shader reads `get_front_word`, then
+/// `get_back_word` if two-sided, then `get_write_mask_word` if either write
+/// mask isn't 0x1, 0x3, 0xf or 0xff.
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub struct StencilConfigUniform {
+    pub front: StencilFaceConfig,
+    pub back: StencilFaceConfig,
+}
+
+impl StencilConfigUniform {
+    pub fn is_two_sided(&self) -> bool {
+        self.front != self.back
+    }
+
+    pub fn needs_write_mask_word(&self) -> bool {
+        self.front.write_mask_code().is_none() || self.back.write_mask_code().is_none()
+    }
+
+    pub fn get_front_word(&self) -> u32 {
+        self.front.get_word(if self.is_two_sided() { 1 } else { 3 })
+    }
+
+    pub fn get_back_word(&self) -> u32 {
+        self.back.get_word(2)
+    }
+
+    pub fn get_write_mask_word(&self) -> u32 {
+        (self.front.write_mask as u32) | ((self.back.write_mask as u32) << 8)
+    }
 }
@@ -4,5 +4,6 @@ pub mod qpu;
 
 pub use drm;
 pub use tokio;
+pub use vc4_ctx;
 pub use vc4_image_addr;
 pub use vc4_image_addr::glam;
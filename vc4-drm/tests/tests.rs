@@ -0,0 +1,163 @@
+use vc4_drm::cl::*;
+
+/// Words in the order the fragment shader reads them.
+fn stencil_words(stencil: &StencilConfigUniform) -> Vec<u32> {
+    let mut words = vec![stencil.get_front_word()];
+    if stencil.is_two_sided() {
+        words.push(stencil.get_back_word());
+    }
+    if stencil.needs_write_mask_word() {
+        words.push(stencil.get_write_mask_word());
+    }
+    words
+}
+
+fn clip_face() -> StencilFaceConfig {
+    StencilFaceConfig {
+        compare_function: CompareFunction::Equal,
+        fail_op: StencilOp::Keep,
+        depth_fail_op: StencilOp::Keep,
+        pass_op: StencilOp::Replace,
+        reference: 1,
+        value_mask: 0xff,
+        write_mask: 0xff,
+    }
+}
+
+fn back_face() -> StencilFaceConfig {
+    StencilFaceConfig {
+        compare_function: CompareFunction::NotEqual,
+        fail_op: StencilOp::Zero,
+        depth_fail_op: StencilOp::Keep,
+        pass_op: StencilOp::IncrWrap,
+        reference: 0x80,
+        value_mask: 0x0f,
+        write_mask: 0x0f,
+    }
+}
+
+// Expected words are those of Mesa's `vc4_get_stencil_config`, with the
+// references ORed in like `vc4_write_uniforms` does.
+
+#[test]
+fn stencil_one_sided() {
+    let stencil = StencilConfigUniform {
+        front: clip_face(),
+        back: clip_face(),
+    };
+    assert_eq!(stencil_words(&stencil), [0xf28a01ff]);
+
+    // A write mask the config word can't hold needs the extra word.
+    let face = StencilFaceConfig {
+        compare_function: CompareFunction::Always,
+        depth_fail_op: StencilOp::Decr,
+        pass_op: StencilOp::Incr,
+        reference: 0,
+        write_mask: 0x3c,
+        ..clip_face()
+    };
+    let stencil = StencilConfigUniform {
+        front: face,
+        back: face,
+    };
+    assert_eq!(stencil_words(&stencil), [0xc8cf00ff, 0x3c3c]);
+}
+
+#[test]
+fn stencil_two_sided() {
+    let stencil = StencilConfigUniform {
+        front: clip_face(),
+        back: back_face(),
+    };
+    assert_eq!(stencil_words(&stencil), [0x728a01ff, 0xa385800f]);
+}
+
+#[test]
+fn stencil_separate_write_masks() {
+    let stencil = StencilConfigUniform {
+        front: StencilFaceConfig {
+            write_mask: 0x7f,
+            ..clip_face()
+        },
+        back: back_face(),
+    };
+    assert_eq!(stencil_words(&stencil), [0x428a01ff, 0xa385800f, 0x0f7f]);
+}
+
+fn texture_words(config: &TextureConfigUniform, explicit_lod: bool) -> Vec<u32> {
+    let mut words = Vec::new();
+    config.write_words(explicit_lod, &mut words);
+    words
+}
+
+fn mipped_texture() -> TextureConfigUniform {
+    TextureConfigUniform {
+        base_address: 3,
+        data_type: TextureDataType::RGBA8888,
+        num_mips: 4,
+        width: 64,
+        height: 32,
+        min_filt: TextureMinFilterType::LinearMipLinear,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn texture_2d_reads_p0_p1() {
+    assert_eq!(
+        texture_words(&mipped_texture(), false),
+        [0x00003003, 0x02004050]
+    );
+}
+
+#[test]
+fn texture_explicit_lod_reads_p2() {
+    assert_eq!(
+        texture_words(&mipped_texture(), true),
+        [0x00003003, 0x02004050, 0x40000001]
+    );
+}
+
+#[test]
+fn texture_cube_map_reads_stride() {
+    let config = TextureConfigUniform {
+        cube_map: true,
+        cube_map_stride: 0x6000,
+        ..mipped_texture()
+    };
+    assert_eq!(
+        texture_words(&config, false),
+        [0x00003203, 0x02004050, 0x40006000]
+    );
+}
+
+#[test]
+fn texture_border_color_precedes_p0() {
+    let config = TextureConfigUniform {
+        wrap_s: TextureWrapType::Border,
+        wrap_t: TextureWrapType::Clamp,
+        border_color: 0xff00ff00,
+        ..mipped_texture()
+    };
+    assert_eq!(
+        texture_words(&config, false),
+        [0xff00ff00, 0x00003003, 0x02004057]
+    );
+}
+
+#[test]
+fn texture_child_image_reads_dimensions_and_offsets() {
+    let config = TextureConfigUniform {
+        child_image: Some(TextureChildImage {
+            width: 48,
+            height: 24,
+            x_offset: 16,
+            y_offset: 8,
+        }),
+        ..mipped_texture()
+    };
+    assert_eq!(
+        texture_words(&config, false),
+        [0x00003003, 0x02004050, 0x80018030, 0xc0008010]
+    );
+}
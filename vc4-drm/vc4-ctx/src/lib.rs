@@ -0,0 +1,242 @@
+//! `.ctx` texture container: a fixed little-endian header followed by the
+//! zlib-compressed contents of the texture BO.
+//!
+//! The BO holds every mip level tiled the way the TMU reads it, smallest
+//! level first, with the base level starting on a page boundary. Cube maps
+//! repeat that layout for each face at `cube_map_stride`.
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+pub const MAGIC: u32 = 0x58544356;
+pub const VERSION: u16 = 2;
+
+/// Magics of the unversioned format, which had a shorter header.
+const LEGACY_MAGIC: u32 = 0x005072C2;
+const LEGACY_CUBE_MAGIC: u32 = 0x005072C3;
+
+const FLAG_CUBE_MAP: u16 = 1 << 0;
+const FLAG_ETC_FLIP: u16 = 1 << 1;
+
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CtxTiling {
+    /// T-format, except for levels small enough to use LT-format. This is
+    /// the rule the TMU applies by itself.
+    #[default]
+    Auto = 0,
+    T = 1,
+    LT = 2,
+}
+
+/// Header of a `.ctx` file. `data_type`, the filters and the wraps hold the
+/// raw hardware values of the texture config fields.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CtxHeader {
+    /// Size of the uncompressed data, which is also the BO size.
+    pub data_size: u32,
+    pub width: u16,
+    pub height: u16,
+    pub num_mips: u8,
+    pub data_type: u8,
+    pub tiling: CtxTiling,
+    pub mag_filt: u8,
+    pub min_filt: u8,
+    pub wrap_s: u8,
+    pub wrap_t: u8,
+    /// Page of the base level, the texture's base address.
+    pub mip0_page_offset: u32,
+    /// Set for ETC1 textures whose blocks are stored top-down, with rows
+    /// stored bottom-up.
+    pub etc_flip: bool,
+    pub cube_map: bool,
+    /// Bytes between cube map faces, 0 for 2D textures.
+    pub cube_map_stride: u32,
+}
+
+#[derive(Debug)]
+pub enum CtxError {
+    Io(io::Error),
+    BadMagic(u32),
+    UnsupportedVersion(u16),
+    /// The file ended before the header or the data was complete.
+    Truncated,
+    InvalidField(&'static str),
+}
+
+impl fmt::Display for CtxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CtxError::Io(err) => write!(f, "{err}"),
+            CtxError::BadMagic(magic) => write!(f, "not a ctx file (magic {magic:#010x})"),
+            CtxError::UnsupportedVersion(version) => {
+                write!(f, "unsupported ctx version {version}, expected {VERSION}")
+            }
+            CtxError::Truncated => write!(f, "ctx file is truncated"),
+            CtxError::InvalidField(field) => write!(f, "invalid ctx header field `{field}`"),
+        }
+    }
+}
+
+impl std::error::Error for CtxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CtxError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CtxError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            CtxError::Truncated
+        } else {
+            CtxError::Io(err)
+        }
+    }
+}
+
+impl CtxHeader {
+    pub const SIZE: usize = 32;
+
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, CtxError> {
+        let mut buf = [0_u8; Self::SIZE];
+        reader.read_exact(&mut buf[0..4])?;
+        let magic = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+        match magic {
+            MAGIC => {}
+            LEGACY_MAGIC | LEGACY_CUBE_MAGIC => return Err(CtxError::UnsupportedVersion(1)),
+            _ => return Err(CtxError::BadMagic(magic)),
+        }
+        reader.read_exact(&mut buf[4..6])?;
+        let version = u16::from_le_bytes(buf[4..6].try_into().unwrap());
+        if version != VERSION {
+            return Err(CtxError::UnsupportedVersion(version));
+        }
+        reader.read_exact(&mut buf[6..])?;
+
+        let flags = u16::from_le_bytes(buf[6..8].try_into().unwrap());
+        let tiling = match buf[18] {
+            0 => CtxTiling::Auto,
+            1 => CtxTiling::T,
+            2 => CtxTiling::LT,
+            _ => return Err(CtxError::InvalidField("tiling")),
+        };
+        let header = Self {
+            data_size: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
+            width: u16::from_le_bytes(buf[12..14].try_into().unwrap()),
+            height: u16::from_le_bytes(buf[14..16].try_into().unwrap()),
+            num_mips: buf[16],
+            data_type: buf[17],
+            tiling,
+            mag_filt: buf[19],
+            min_filt: buf[20],
+            wrap_s: buf[21],
+            wrap_t: buf[22],
+            mip0_page_offset: u32::from_le_bytes(buf[24..28].try_into().unwrap()),
+            etc_flip: flags & FLAG_ETC_FLIP != 0,
+            cube_map: flags & FLAG_CUBE_MAP != 0,
+            cube_map_stride: u32::from_le_bytes(buf[28..32].try_into().unwrap()),
+        };
+        header.validate()?;
+        Ok(header)
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), CtxError> {
+        self.validate()?;
+
+        let mut buf = [0_u8; Self::SIZE];
+        buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        let mut flags = 0;
+        if self.cube_map {
+            flags |= FLAG_CUBE_MAP;
+        }
+        if self.etc_flip {
+            flags |= FLAG_ETC_FLIP;
+        }
+        buf[6..8].copy_from_slice(&flags.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.data_size.to_le_bytes());
+        buf[12..14].copy_from_slice(&self.width.to_le_bytes());
+        buf[14..16].copy_from_slice(&self.height.to_le_bytes());
+        buf[16] = self.num_mips;
+        buf[17] = self.data_type;
+        buf[18] = self.tiling as u8;
+        buf[19] = self.mag_filt;
+        buf[20] = self.min_filt;
+        buf[21] = self.wrap_s;
+        buf[22] = self.wrap_t;
+        buf[24..28].copy_from_slice(&self.mip0_page_offset.to_le_bytes());
+        buf[28..32].copy_from_slice(&self.cube_map_stride.to_le_bytes());
+        writer.write_all(&buf)?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), CtxError> {
+        if self.width == 0 || self.height == 0 {
+            return Err(CtxError::InvalidField("width/height"));
+        }
+        if self.num_mips == 0 || self.num_mips > 16 {
+            return Err(CtxError::InvalidField("num_mips"));
+        }
+        // Only ETC1 (`TextureDataType::ETC1`) textures are stored in blocks.
+        if self.etc_flip && self.data_type != 8 {
+            return Err(CtxError::InvalidField("etc_flip"));
+        }
+        if self.cube_map != (self.cube_map_stride != 0) {
+            return Err(CtxError::InvalidField("cube_map_stride"));
+        }
+        if !self.cube_map_stride.is_multiple_of(4096) {
+            return Err(CtxError::InvalidField("cube_map_stride"));
+        }
+        Ok(())
+    }
+}
+
+/// Decompresses the data following the header into `out`, which must be
+/// `header.data_size` bytes long (for instance a BO mapping).
+pub fn read_data<R: Read>(reader: R, header: &CtxHeader, out: &mut [u8]) -> Result<(), CtxError> {
+    assert_eq!(out.len(), header.data_size as usize);
+    let mut d = ZlibDecoder::new(reader);
+    d.read_exact(out)?;
+    Ok(())
+}
+
+/// Reads a whole `.ctx` file into memory.
+pub fn read<R: Read>(mut reader: R) -> Result<(CtxHeader, Vec<u8>), CtxError> {
+    let header = CtxHeader::read(&mut reader)?;
+    let mut data = vec![0_u8; header.data_size as usize];
+    read_data(reader, &header, &mut data)?;
+    Ok((header, data))
+}
+
+/// Writes `header` and the compressed `data`. `header.data_size` must match
+/// the data length.
+pub fn write<W: Write>(mut writer: W, header: &CtxHeader, data: &[u8]) -> Result<(), CtxError> {
+    if header.data_size as usize != data.len() {
+        return Err(CtxError::InvalidField("data_size"));
+    }
+    header.write(&mut writer)?;
+    let mut c = ZlibEncoder::new(writer, flate2::Compression::best());
+    c.write_all(data)?;
+    c.finish()?;
+    Ok(())
+}
+
+/// Returns the version of the `.ctx` file read by `reader` without
+/// validating the rest of it, or `None` if it isn't a `.ctx` file.
+pub fn peek_version<R: Read>(mut reader: R) -> Option<u16> {
+    let mut buf = [0_u8; 6];
+    reader.read_exact(&mut buf[0..4]).ok()?;
+    match u32::from_le_bytes(buf[0..4].try_into().unwrap()) {
+        MAGIC => {
+            reader.read_exact(&mut buf[4..6]).ok()?;
+            Some(u16::from_le_bytes(buf[4..6].try_into().unwrap()))
+        }
+        LEGACY_MAGIC | LEGACY_CUBE_MAGIC => Some(1),
+        _ => None,
+    }
+}
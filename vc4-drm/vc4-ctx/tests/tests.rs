@@ -0,0 +1,154 @@
+use vc4_ctx::*;
+
+fn test_header(data_size: u32) -> CtxHeader {
+    CtxHeader {
+        data_size,
+        width: 64,
+        height: 32,
+        num_mips: 6,
+        data_type: 0,
+        tiling: CtxTiling::Auto,
+        mag_filt: 0,
+        min_filt: 5,
+        wrap_s: 0,
+        wrap_t: 1,
+        mip0_page_offset: 1,
+        etc_flip: false,
+        cube_map: false,
+        cube_map_stride: 0,
+    }
+}
+
+fn test_data(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i * 7 + i / 251) as u8).collect()
+}
+
+fn write_to_vec(header: &CtxHeader, data: &[u8]) -> Vec<u8> {
+    let mut file = Vec::new();
+    write(&mut file, header, data).unwrap();
+    file
+}
+
+#[test]
+fn ctx_round_trip() {
+    let header = test_header(12288);
+    let data = test_data(12288);
+    let file = write_to_vec(&header, &data);
+
+    let (read_header, read_data) = read(file.as_slice()).unwrap();
+    assert_eq!(read_header, header);
+    assert_eq!(read_data, data);
+}
+
+#[test]
+fn ctx_round_trip_cube_map() {
+    let header = CtxHeader {
+        width: 32,
+        height: 32,
+        cube_map: true,
+        cube_map_stride: 8192,
+        ..test_header(6 * 8192)
+    };
+    let data = test_data(6 * 8192);
+    let file = write_to_vec(&header, &data);
+
+    let (read_header, read_data) = read(file.as_slice()).unwrap();
+    assert_eq!(read_header, header);
+    assert_eq!(read_data, data);
+}
+
+#[test]
+fn ctx_round_trip_etc_flip() {
+    let header = CtxHeader {
+        data_type: 8,
+        etc_flip: true,
+        ..test_header(8192)
+    };
+    let file = write_to_vec(&header, &test_data(8192));
+
+    let (read_header, _) = read(file.as_slice()).unwrap();
+    assert_eq!(read_header, header);
+}
+
+#[test]
+fn ctx_header_size() {
+    let file = write_to_vec(&test_header(0), &[]);
+    let mut reader = file.as_slice();
+    CtxHeader::read(&mut reader).unwrap();
+    assert_eq!(file.len() - reader.len(), CtxHeader::SIZE);
+}
+
+#[test]
+fn ctx_bad_magic() {
+    let mut file = write_to_vec(&test_header(16), &test_data(16));
+    file[0..4].copy_from_slice(&0x89504e47_u32.to_le_bytes());
+    assert!(matches!(
+        read(file.as_slice()),
+        Err(CtxError::BadMagic(0x89504e47))
+    ));
+    assert_eq!(peek_version(file.as_slice()), None);
+}
+
+#[test]
+fn ctx_legacy_version() {
+    let mut file = vec![0_u8; 16];
+    file[0..4].copy_from_slice(&0x005072C2_u32.to_le_bytes());
+    assert!(matches!(
+        read(file.as_slice()),
+        Err(CtxError::UnsupportedVersion(1))
+    ));
+    assert_eq!(peek_version(file.as_slice()), Some(1));
+}
+
+#[test]
+fn ctx_future_version() {
+    let mut file = write_to_vec(&test_header(16), &test_data(16));
+    file[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+    assert!(matches!(
+        read(file.as_slice()),
+        Err(CtxError::UnsupportedVersion(v)) if v == VERSION + 1
+    ));
+    assert_eq!(peek_version(file.as_slice()), Some(VERSION + 1));
+}
+
+#[test]
+fn ctx_truncated_header() {
+    let file = write_to_vec(&test_header(16), &test_data(16));
+    assert!(matches!(
+        read(&file[..CtxHeader::SIZE - 1]),
+        Err(CtxError::Truncated)
+    ));
+    assert!(matches!(read(&file[..2]), Err(CtxError::Truncated)));
+}
+
+#[test]
+fn ctx_truncated_data() {
+    let file = write_to_vec(&test_header(4096), &test_data(4096));
+    assert!(matches!(
+        read(&file[..file.len() - 8]),
+        Err(CtxError::Truncated)
+    ));
+}
+
+#[test]
+fn ctx_data_size_mismatch() {
+    let mut file = Vec::new();
+    assert!(matches!(
+        write(&mut file, &test_header(32), &test_data(16)),
+        Err(CtxError::InvalidField("data_size"))
+    ));
+}
+
+#[test]
+fn ctx_invalid_cube_map_stride() {
+    let header = CtxHeader {
+        cube_map: true,
+        cube_map_stride: 1000,
+        ..test_header(6000)
+    };
+    let mut file = Vec::new();
+    assert!(matches!(
+        write(&mut file, &header, &test_data(6000)),
+        Err(CtxError::InvalidField("cube_map_stride"))
+    ));
+}
@@ -101,15 +101,59 @@ pub struct TileAddress {
     pub bit: u8,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TranslatorError {
+    UnsupportedBpp(u32),
+    ZeroSize(UVec2),
+    /// The tiled image wouldn't fit in 4 GiB.
+    TooLarge(UVec2),
+    OutOfBounds {
+        coord: UVec2,
+        image_size: UVec2,
+    },
+}
+
+impl std::fmt::Display for TranslatorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TranslatorError::UnsupportedBpp(bpp) => write!(f, "unsupported bpp {bpp}"),
+            TranslatorError::ZeroSize(size) => write!(f, "empty {}x{} image", size.x, size.y),
+            TranslatorError::TooLarge(size) => {
+                write!(f, "{}x{} image is too large", size.x, size.y)
+            }
+            TranslatorError::OutOfBounds { coord, image_size } => write!(
+                f,
+                "({}, {}) is outside of the {}x{} image",
+                coord.x, coord.y, image_size.x, image_size.y
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TranslatorError {}
+
 #[enum_dispatch]
 pub trait TranslatorTrait {
     fn coordinate_to_tile_address(&self, coord: UVec2) -> TileAddress;
+    /// Like `coordinate_to_tile_address`, checking that `coord` is within
+    /// the image even in release builds.
+    fn try_coordinate_to_tile_address(&self, coord: UVec2) -> Result<TileAddress, TranslatorError> {
+        let image_size = self.image_size();
+        if coord.x >= image_size.x || coord.y >= image_size.y {
+            return Err(TranslatorError::OutOfBounds { coord, image_size });
+        }
+        Ok(self.coordinate_to_tile_address(coord))
+    }
     fn tile_address_to_coordinate(&self, address: TileAddress) -> UVec2;
     fn is_lt_format(&self) -> bool;
+    fn image_size(&self) -> UVec2;
+    /// Size in pixels of a utile, the 64-byte block both tilings are made
+    /// of.
+    fn utile_size(&self) -> UVec2;
+    fn bpp(&self) -> u32;
 }
 
 pub struct TTranslator<Fac: U32Factor> {
-    #[cfg(debug_assertions)]
     image_size: UVec2,
     utile_size: UVec2PowerOfTwoFactor,
     size_in_tile_x: Fac,
@@ -205,10 +249,21 @@ impl<Fac: U32Factor> TranslatorTrait for TTranslator<Fac> {
     fn is_lt_format(&self) -> bool {
         false
     }
+
+    fn image_size(&self) -> UVec2 {
+        self.image_size
+    }
+
+    fn utile_size(&self) -> UVec2 {
+        UVec2::ONE << self.utile_size.shift
+    }
+
+    fn bpp(&self) -> u32 {
+        1 << self.bpp_shift
+    }
 }
 
 pub struct LTTranslator<Fac: U32Factor> {
-    #[cfg(debug_assertions)]
     image_size: UVec2,
     utile_size: UVec2PowerOfTwoFactor,
     size_in_utile_x: Fac,
@@ -254,6 +309,18 @@ impl<Fac: U32Factor> TranslatorTrait for LTTranslator<Fac> {
     fn is_lt_format(&self) -> bool {
         true
     }
+
+    fn image_size(&self) -> UVec2 {
+        self.image_size
+    }
+
+    fn utile_size(&self) -> UVec2 {
+        UVec2::ONE << self.utile_size.shift
+    }
+
+    fn bpp(&self) -> u32 {
+        1 << self.bpp_shift
+    }
 }
 
 #[enum_dispatch(TranslatorTrait)]
@@ -266,7 +333,14 @@ pub enum Translator {
 
 impl Translator {
     const fn utile_size_and_bpp_shift(bpp: u32) -> (UVec2PowerOfTwoFactor, u32) {
-        match bpp {
+        match Self::try_utile_size_and_bpp_shift(bpp) {
+            Some(utile_size_and_bpp_shift) => utile_size_and_bpp_shift,
+            None => panic!("Unexpected bpp"),
+        }
+    }
+
+    const fn try_utile_size_and_bpp_shift(bpp: u32) -> Option<(UVec2PowerOfTwoFactor, u32)> {
+        Some(match bpp {
             64 => (
                 UVec2PowerOfTwoFactor {
                     shift: UVec2::new(1, 2),
@@ -309,10 +383,8 @@ impl Translator {
                 },
                 0,
             ),
-            _ => {
-                panic!("Unexpected bpp");
-            }
-        }
+            _ => return None,
+        })
     }
 
     pub fn new_with_alloc_size(image_size: UVec2, bpp: u32) -> (Self, u32) {
@@ -322,7 +394,6 @@ impl Translator {
             let size_in_utile = utile_size.round_up_div(image_size);
             let translator = if size_in_utile.x.is_power_of_two() {
                 LTTranslator {
-                    #[cfg(debug_assertions)]
                     image_size,
                     utile_size,
                     size_in_utile_x: U32PowerOfTwoFactor {
@@ -334,7 +405,6 @@ impl Translator {
                 .into()
             } else {
                 LTTranslator {
-                    #[cfg(debug_assertions)]
                     image_size,
                     utile_size,
                     size_in_utile_x: U32NonPowerOfTwoFactor(size_in_utile.x),
@@ -349,7 +419,6 @@ impl Translator {
             let size_in_tile = SPLAT_2.round_up_div(size_in_subtile);
             let translator = if size_in_tile.x.is_power_of_two() {
                 TTranslator {
-                    #[cfg(debug_assertions)]
                     image_size,
                     utile_size,
                     size_in_tile_x: U32PowerOfTwoFactor {
@@ -361,7 +430,6 @@ impl Translator {
                 .into()
             } else {
                 TTranslator {
-                    #[cfg(debug_assertions)]
                     image_size,
                     utile_size,
                     size_in_tile_x: U32NonPowerOfTwoFactor(size_in_tile.x),
@@ -380,4 +448,343 @@ impl Translator {
     pub fn alloc_size(image_size: UVec2, bpp: u32) -> u32 {
         Self::new_with_alloc_size(image_size, bpp).1
     }
+
+    /// Like `new_with_alloc_size`, for sizes and bpp that can't be trusted,
+    /// such as those read from files.
+    pub fn try_new_with_alloc_size(
+        image_size: UVec2,
+        bpp: u32,
+    ) -> Result<(Self, u32), TranslatorError> {
+        let Some((utile_size, _)) = Self::try_utile_size_and_bpp_shift(bpp) else {
+            return Err(TranslatorError::UnsupportedBpp(bpp));
+        };
+        if image_size.x == 0 || image_size.y == 0 {
+            return Err(TranslatorError::ZeroSize(image_size));
+        }
+        // T-format pads to whole tiles, more than LT-format ever does.
+        let tile_size = (UVec2::ONE << utile_size.shift) * 8;
+        let size_in_tile = UVec2::new(
+            image_size.x.div_ceil(tile_size.x),
+            image_size.y.div_ceil(tile_size.y),
+        );
+        if size_in_tile.x as u64 * size_in_tile.y as u64 * 4096 > u32::MAX as u64 {
+            return Err(TranslatorError::TooLarge(image_size));
+        }
+        Ok(Self::new_with_alloc_size(image_size, bpp))
+    }
+
+    pub fn try_new(image_size: UVec2, bpp: u32) -> Result<Self, TranslatorError> {
+        Ok(Self::try_new_with_alloc_size(image_size, bpp)?.0)
+    }
+}
+
+/// Copies `len` bits from `src` to `dst`, both offsets in bits. Pixels
+/// narrower than a byte are packed from the least significant bit, like
+/// in tiled images.
+fn copy_bits(src: &[u8], src_bit: usize, dst: &mut [u8], dst_bit: usize, len: usize) {
+    if src_bit.is_multiple_of(8) && dst_bit.is_multiple_of(8) && len.is_multiple_of(8) {
+        let (src_byte, dst_byte, len) = (src_bit / 8, dst_bit / 8, len / 8);
+        dst[dst_byte..dst_byte + len].copy_from_slice(&src[src_byte..src_byte + len]);
+        return;
+    }
+    for i in 0..len {
+        let (s, d) = (src_bit + i, dst_bit + i);
+        let bit = (src[s / 8] >> (s % 8)) & 1;
+        dst[d / 8] = (dst[d / 8] & !(1 << (d % 8))) | (bit << (d % 8));
+    }
+}
+
+impl Translator {
+    /// Utiles covering any pixel of the `size` rectangle at `origin`, row
+    /// by row. In T-format, the 4 KiB tile of a utile starts at
+    /// `offset & !4095`.
+    pub fn touched_utiles(
+        &self,
+        origin: UVec2,
+        size: UVec2,
+    ) -> impl Iterator<Item = TouchedUtile> + '_ {
+        let end = origin + size;
+        assert!(end.x <= self.image_size().x && end.y <= self.image_size().y);
+        let utile_size = self.utile_size();
+        let first_utile = origin / utile_size;
+        let end_utile = if size.x == 0 || size.y == 0 {
+            first_utile
+        } else {
+            (end + utile_size - UVec2::ONE) / utile_size
+        };
+        (first_utile.y..end_utile.y).flat_map(move |utile_y| {
+            (first_utile.x..end_utile.x).map(move |utile_x| {
+                let utile_origin = UVec2::new(utile_x, utile_y) * utile_size;
+                TouchedUtile {
+                    offset: self.coordinate_to_tile_address(utile_origin).offset,
+                    origin: utile_origin,
+                    min: UVec2::max(utile_origin, origin) - utile_origin,
+                    max: UVec2::min(utile_origin + utile_size, end) - utile_origin,
+                }
+            })
+        })
+    }
+
+    /// Runs of pixels of `utile` within the rectangle at `origin`, one per
+    /// utile row, which are contiguous in both images. Yields the bit
+    /// offset of each run in the utile, its bit offset in the linear image,
+    /// which holds only the rectangle with rows `stride` bytes apart, and
+    /// its length in bits.
+    fn utile_rows(
+        &self,
+        utile: TouchedUtile,
+        origin: UVec2,
+        stride: usize,
+    ) -> impl Iterator<Item = (usize, usize, usize)> {
+        let utile_width = self.utile_size().x;
+        let bpp = self.bpp() as usize;
+        let len = (utile.max.x - utile.min.x) as usize * bpp;
+        (utile.min.y..utile.max.y).map(move |y| {
+            let bit_in_utile = (y * utile_width + utile.min.x) as usize * bpp;
+            let linear = utile.origin + UVec2::new(utile.min.x, y) - origin;
+            let linear_bit = linear.y as usize * stride * 8 + linear.x as usize * bpp;
+            (bit_in_utile, linear_bit, len)
+        })
+    }
+
+    /// Tiles the whole linear image `src`, whose rows are `src_stride`
+    /// bytes apart, into `dst`.
+    pub fn tile(&self, src: &[u8], src_stride: usize, dst: &mut [u8]) {
+        self.tile_rect(src, src_stride, UVec2::ZERO, self.image_size(), dst);
+    }
+
+    /// Byte offset in the linear image of `utile`, whose rows are `stride`
+    /// bytes apart, if the rectangle at `origin` covers all of it and its
+    /// rows start on whole bytes, so that it can be copied as whole bytes.
+    fn whole_utile_offset(
+        &self,
+        utile: &TouchedUtile,
+        origin: UVec2,
+        stride: usize,
+    ) -> Option<usize> {
+        if !utile.is_full(self.utile_size()) {
+            return None;
+        }
+        let linear = utile.origin - origin;
+        let bit = linear.x as usize * self.bpp() as usize;
+        bit.is_multiple_of(8)
+            .then(|| linear.y as usize * stride + bit / 8)
+    }
+
+    /// Tiles the linear `src` image of `size` pixels, whose rows are
+    /// `src_stride` bytes apart, into the rectangle at `origin` of `dst`.
+    /// The rest of `dst` is left untouched. Utiles inside the rectangle are
+    /// written as 64 whole bytes, the others utile row by utile row.
+    pub fn tile_rect(
+        &self,
+        src: &[u8],
+        src_stride: usize,
+        origin: UVec2,
+        size: UVec2,
+        dst: &mut [u8],
+    ) {
+        let row_bytes = 64 / self.utile_size().y as usize;
+        for utile in self.touched_utiles(origin, size) {
+            if let Some(linear) = self.whole_utile_offset(&utile, origin, src_stride) {
+                let utile_data = &mut dst[utile.offset as usize..][..64];
+                for (row, utile_row) in utile_data.chunks_exact_mut(row_bytes).enumerate() {
+                    utile_row.copy_from_slice(&src[linear + row * src_stride..][..row_bytes]);
+                }
+                continue;
+            }
+            let utile_bit = utile.offset as usize * 8;
+            for (bit, linear_bit, len) in self.utile_rows(utile, origin, src_stride) {
+                copy_bits(src, linear_bit, dst, utile_bit + bit, len);
+            }
+        }
+    }
+
+    /// Untiles the whole image `src` into the linear `dst`, whose rows are
+    /// `dst_stride` bytes apart.
+    pub fn untile(&self, src: &[u8], dst: &mut [u8], dst_stride: usize) {
+        self.untile_rect(src, UVec2::ZERO, self.image_size(), dst, dst_stride);
+    }
+
+    /// Untiles the rectangle of `size` pixels at `origin` of `src` into the
+    /// linear `dst`, whose rows are `dst_stride` bytes apart. Like
+    /// [`Translator::tile_rect`], utiles inside the rectangle are read as 64
+    /// whole bytes.
+    pub fn untile_rect(
+        &self,
+        src: &[u8],
+        origin: UVec2,
+        size: UVec2,
+        dst: &mut [u8],
+        dst_stride: usize,
+    ) {
+        let row_bytes = 64 / self.utile_size().y as usize;
+        for utile in self.touched_utiles(origin, size) {
+            if let Some(linear) = self.whole_utile_offset(&utile, origin, dst_stride) {
+                let utile_data = &src[utile.offset as usize..][..64];
+                for (row, utile_row) in utile_data.chunks_exact(row_bytes).enumerate() {
+                    dst[linear + row * dst_stride..][..row_bytes].copy_from_slice(utile_row);
+                }
+                continue;
+            }
+            let utile_bit = utile.offset as usize * 8;
+            for (bit, linear_bit, len) in self.utile_rows(utile, origin, dst_stride) {
+                copy_bits(src, utile_bit + bit, dst, linear_bit, len);
+            }
+        }
+    }
+
+    /// Writes the linear `src` image of `size` pixels, whose rows are
+    /// `src_stride` bytes apart, into the rectangle at `origin` of `dst`,
+    /// like [`Translator::tile_rect`], but writing each touched utile whole.
+    /// Utiles the rectangle only partly covers are read first and written
+    /// back with their other pixels unchanged.
+    ///
+    /// Whole 64-byte writes suit write-combined BO mappings, where small
+    /// scattered writes are slow.
+    pub fn update_rect(
+        &self,
+        src: &[u8],
+        src_stride: usize,
+        origin: UVec2,
+        size: UVec2,
+        dst: &mut [u8],
+    ) {
+        let utile_size = self.utile_size();
+        for utile in self.touched_utiles(origin, size) {
+            let range = utile.offset as usize..utile.offset as usize + 64;
+            let mut utile_data = [0_u8; 64];
+            if !utile.is_full(utile_size) {
+                utile_data.copy_from_slice(&dst[range.clone()]);
+            }
+            for (bit, linear_bit, len) in self.utile_rows(utile, origin, src_stride) {
+                copy_bits(src, linear_bit, &mut utile_data, bit, len);
+            }
+            dst[range].copy_from_slice(&utile_data);
+        }
+    }
+}
+
+/// A utile touched by a rectangle, see [`Translator::touched_utiles`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TouchedUtile {
+    /// Byte offset of the utile in the tiled image.
+    pub offset: u32,
+    /// Coordinate of the utile's first pixel.
+    pub origin: UVec2,
+    /// Pixels of the utile within the rectangle, from `min` included to
+    /// `max` excluded, relative to `origin`.
+    pub min: UVec2,
+    pub max: UVec2,
+}
+
+impl TouchedUtile {
+    /// Whether the rectangle covers the whole utile.
+    pub fn is_full(&self, utile_size: UVec2) -> bool {
+        self.min == UVec2::ZERO && self.max == utile_size
+    }
+}
+
+/// One level of a [`MipChainLayout`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MipLevelLayout {
+    /// Size in pixels.
+    pub size: UVec2,
+    /// Offset from the start of the chain.
+    pub offset: u32,
+    pub alloc_size: u32,
+    /// Whether the TMU reads the level in LT-format rather than T-format.
+    pub is_lt_format: bool,
+}
+
+/// Layout of a mip chain in a BO, as the TMU expects it.
+///
+/// Levels past the base one are `pot_size >> level`, clamped to 1. Smaller
+/// levels are stored first so that the base level, stored last, starts on
+/// a page boundary once the front is padded, which is what the texture
+/// config's `base_address` points to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MipChainLayout {
+    /// Levels, base level first.
+    pub levels: Vec<MipLevelLayout>,
+    /// Size of the whole chain, front padding included.
+    pub total_size: u32,
+}
+
+impl MipChainLayout {
+    /// Layout of `num_mips` levels of a `size` image of `bpp` bits per
+    /// pixel.
+    pub fn new(size: UVec2, bpp: u32, num_mips: u32) -> Self {
+        Self::try_new(size, bpp, num_mips).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Layout of an ETC1 mip chain, where each level is tiled as a grid of
+    /// 64bpp 4x4 blocks. Levels below 4x4 still take a whole block.
+    pub fn new_etc1(size: UVec2, num_mips: u32) -> Self {
+        Self::try_new_etc1(size, num_mips).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Like `new`, for sizes and bpp read from files.
+    pub fn try_new(size: UVec2, bpp: u32, num_mips: u32) -> Result<Self, TranslatorError> {
+        Self::try_with_tiled_size(size, num_mips, |level_size| {
+            Translator::try_new_with_alloc_size(level_size, bpp)
+        })
+    }
+
+    /// Like `new_etc1`, for sizes read from files.
+    pub fn try_new_etc1(size: UVec2, num_mips: u32) -> Result<Self, TranslatorError> {
+        Self::try_with_tiled_size(size, num_mips, |level_size| {
+            let size_in_blocks = UVec2::new(level_size.x.div_ceil(4), level_size.y.div_ceil(4));
+            Translator::try_new_with_alloc_size(size_in_blocks, 64)
+        })
+    }
+
+    fn try_with_tiled_size(
+        size: UVec2,
+        num_mips: u32,
+        translator: impl Fn(UVec2) -> Result<(Translator, u32), TranslatorError>,
+    ) -> Result<Self, TranslatorError> {
+        assert!(num_mips > 0, "a mip chain has at least one level");
+        let pot_size = UVec2::new(size.x.next_power_of_two(), size.y.next_power_of_two());
+        let mut levels = (0..num_mips)
+            .map(|level| {
+                let size = if level == 0 {
+                    size
+                } else {
+                    UVec2::max(UVec2::splat(1), pot_size >> level)
+                };
+                let (translator, alloc_size) = translator(size)?;
+                Ok(MipLevelLayout {
+                    size,
+                    offset: 0,
+                    alloc_size,
+                    is_lt_format: translator.is_lt_format(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let too_large = || TranslatorError::TooLarge(size);
+        let unpadded_size = levels
+            .iter()
+            .try_fold(0_u32, |sum, level| sum.checked_add(level.alloc_size))
+            .ok_or_else(too_large)?;
+        let base_offset = (unpadded_size - levels[0].alloc_size)
+            .checked_next_multiple_of(4096)
+            .ok_or_else(too_large)?;
+        let mut offset = base_offset;
+        for (i, level) in levels.iter_mut().enumerate() {
+            if i > 0 {
+                offset -= level.alloc_size;
+            }
+            level.offset = offset;
+        }
+        let total_size = base_offset
+            .checked_add(levels[0].alloc_size)
+            .ok_or_else(too_large)?;
+        Ok(Self { levels, total_size })
+    }
+
+    /// Page of the base level, the texture config's `base_address`.
+    pub fn base_page(&self) -> u32 {
+        self.levels[0].offset / 4096
+    }
 }
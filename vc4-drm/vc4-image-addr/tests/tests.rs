@@ -1,4 +1,5 @@
 use glam::UVec2;
+use proptest::prelude::*;
 use vc4_image_addr::*;
 
 fn assert_translate(translator: &Translator, x: u32, y: u32, expected_offset: u32) {
@@ -120,3 +121,498 @@ fn vc4_image_32bpp_utiles_lt_pot16() {
     assert_translate(&translator, 0, 7, 4 * 64 + 3 * 16);
     assert_translate(&translator, 7, 7, 5 * 64 + 3 * 16 + 3 * 4);
 }
+
+const ALL_BPP: [u32; 6] = [64, 32, 16, 8, 4, 1];
+
+fn test_bytes(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9e3779b9) | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+fn get_bits(data: &[u8], bit: usize, len: usize) -> u64 {
+    (0..len).fold(0, |value, i| {
+        let b = bit + i;
+        value | (((data[b / 8] >> (b % 8)) & 1) as u64) << i
+    })
+}
+
+fn set_bits(data: &mut [u8], bit: usize, len: usize, value: u64) {
+    for i in 0..len {
+        let b = bit + i;
+        data[b / 8] = (data[b / 8] & !(1 << (b % 8))) | (((value >> i) & 1) as u8) << (b % 8);
+    }
+}
+
+/// Per-pixel tiling of a rectangle, the reference for the bulk copies.
+fn tile_rect_per_pixel(
+    translator: &Translator,
+    src: &[u8],
+    src_stride: usize,
+    origin: UVec2,
+    size: UVec2,
+    dst: &mut [u8],
+) {
+    let bpp = translator.bpp() as usize;
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let address = translator.coordinate_to_tile_address(origin + UVec2::new(x, y));
+            let linear_bit = y as usize * src_stride * 8 + x as usize * bpp;
+            let value = get_bits(src, linear_bit, bpp);
+            set_bits(
+                dst,
+                address.offset as usize * 8 + address.bit as usize,
+                bpp,
+                value,
+            );
+        }
+    }
+}
+
+/// Compares the pixels of two linear images, ignoring the padding bits of
+/// partial bytes and of the stride.
+fn assert_linear_eq(a: &[u8], b: &[u8], stride: usize, size: UVec2, bpp: u32) {
+    let len = bpp.min(8) as usize;
+    for y in 0..size.y as usize {
+        for x in (0..(size.x * bpp) as usize).step_by(len) {
+            let bit = y * stride * 8 + x;
+            assert_eq!(
+                get_bits(a, bit, len),
+                get_bits(b, bit, len),
+                "{bpp}bpp {size} bit {x} of row {y}"
+            );
+        }
+    }
+}
+
+/// T and LT, POT and NPOT sizes for every bpp.
+fn bulk_test_sizes() -> Vec<UVec2> {
+    vec![
+        UVec2::new(1, 1),
+        UVec2::new(16, 16),
+        UVec2::new(37, 5),
+        UVec2::new(128, 64),
+        UVec2::new(200, 150),
+        UVec2::new(300, 77),
+    ]
+}
+
+#[test]
+fn vc4_image_bulk_tile_matches_per_pixel() {
+    for bpp in ALL_BPP {
+        for size in bulk_test_sizes() {
+            let (translator, alloc_size) = Translator::new_with_alloc_size(size, bpp);
+            let stride = (size.x * bpp).div_ceil(8) as usize + 3;
+            let src = test_bytes(stride * size.y as usize, size.x * bpp);
+
+            let mut bulk = vec![0_u8; alloc_size as usize];
+            translator.tile(&src, stride, &mut bulk);
+            let mut reference = vec![0_u8; alloc_size as usize];
+            tile_rect_per_pixel(&translator, &src, stride, UVec2::ZERO, size, &mut reference);
+            assert_eq!(bulk, reference, "{bpp}bpp {size}");
+
+            // Only the image's pixels come back: padding bits of partial
+            // bytes and of the stride stay zero.
+            let mut untiled = vec![0_u8; stride * size.y as usize];
+            translator.untile(&bulk, &mut untiled, stride);
+            assert_linear_eq(&untiled, &src, stride, size, bpp);
+        }
+    }
+}
+
+#[test]
+fn vc4_image_bulk_sub_rect() {
+    for bpp in ALL_BPP {
+        for size in bulk_test_sizes() {
+            let (translator, alloc_size) = Translator::new_with_alloc_size(size, bpp);
+            let utile_size = translator.utile_size();
+            // Unaligned to utiles and, below 8bpp, to bytes, then aligned to
+            // utiles so that the utiles inside are copied whole.
+            for origin in [size / 3, size / 3 / utile_size * utile_size] {
+                let rect_size = UVec2::max(UVec2::ONE, size / 2);
+                let rect_size = UVec2::min(rect_size, size - origin);
+                let stride = (rect_size.x * bpp).div_ceil(8) as usize;
+                let src = test_bytes(stride * rect_size.y as usize, bpp + size.y);
+
+                let background = test_bytes(alloc_size as usize, 7);
+                let mut bulk = background.clone();
+                translator.tile_rect(&src, stride, origin, rect_size, &mut bulk);
+                let mut reference = background;
+                tile_rect_per_pixel(&translator, &src, stride, origin, rect_size, &mut reference);
+                assert_eq!(bulk, reference, "{bpp}bpp {size} at {origin}");
+
+                let mut updated = test_bytes(alloc_size as usize, 7);
+                translator.update_rect(&src, stride, origin, rect_size, &mut updated);
+                assert_eq!(updated, reference, "{bpp}bpp {size} at {origin}");
+
+                let mut untiled = vec![0_u8; src.len()];
+                translator.untile_rect(&bulk, origin, rect_size, &mut untiled, stride);
+                assert_linear_eq(&untiled, &src, stride, rect_size, bpp);
+            }
+        }
+    }
+}
+
+#[test]
+fn vc4_image_touched_utiles() {
+    // 32bpp utiles are 4x4: the 6x3 rectangle at (3, 2) touches columns 0
+    // to 2 and rows 0 and 1 of utiles.
+    let translator = Translator::new((64, 64).into(), 32);
+    let utiles: Vec<_> = translator
+        .touched_utiles(UVec2::new(3, 2), UVec2::new(6, 3))
+        .collect();
+    assert_eq!(utiles.len(), 6);
+    assert_eq!(utiles[0].origin, UVec2::new(0, 0));
+    assert_eq!(utiles[0].offset, 0);
+    assert_eq!(
+        (utiles[0].min, utiles[0].max),
+        (UVec2::new(3, 2), UVec2::new(4, 4))
+    );
+    assert_eq!(utiles[1].offset, 64);
+    assert_eq!(
+        (utiles[1].min, utiles[1].max),
+        (UVec2::new(0, 2), UVec2::new(4, 4))
+    );
+    assert_eq!(utiles[5].origin, UVec2::new(8, 4));
+    assert_eq!(
+        (utiles[5].min, utiles[5].max),
+        (UVec2::new(0, 0), UVec2::new(1, 1))
+    );
+    assert!(utiles.iter().all(|utile| !utile.is_full(UVec2::new(4, 4))));
+
+    let utiles: Vec<_> = translator
+        .touched_utiles(UVec2::new(4, 4), UVec2::new(4, 4))
+        .collect();
+    assert_eq!(utiles.len(), 1);
+    assert!(utiles[0].is_full(UVec2::new(4, 4)));
+
+    assert_eq!(
+        translator.touched_utiles(UVec2::ZERO, UVec2::ZERO).count(),
+        0
+    );
+}
+
+fn assert_chain_rules(layout: &MipChainLayout) {
+    let base = &layout.levels[0];
+    assert_eq!(base.offset % 4096, 0);
+    assert_eq!(base.offset + base.alloc_size, layout.total_size);
+    // Every level ends where the next larger one starts, except for the
+    // padding before the base level.
+    for pair in layout.levels[1..].windows(2) {
+        assert_eq!(pair[1].offset + pair[1].alloc_size, pair[0].offset);
+    }
+    if let Some(level_1) = layout.levels.get(1) {
+        assert!(base.offset - (level_1.offset + level_1.alloc_size) < 4096);
+    }
+}
+
+#[test]
+fn vc4_image_mip_chain_layout_pot() {
+    let layout = MipChainLayout::new((64, 64).into(), 32, 7);
+    assert_chain_rules(&layout);
+
+    let sizes: Vec<_> = layout.levels.iter().map(|level| level.size.x).collect();
+    assert_eq!(sizes, [64, 32, 16, 8, 4, 2, 1]);
+    let offsets: Vec<_> = layout.levels.iter().map(|level| level.offset).collect();
+    assert_eq!(offsets, [8192, 4096, 3072, 2816, 2752, 2688, 2624]);
+    let alloc_sizes: Vec<_> = layout.levels.iter().map(|level| level.alloc_size).collect();
+    assert_eq!(alloc_sizes, [16384, 4096, 1024, 256, 64, 64, 64]);
+    assert_eq!(layout.total_size, 24576);
+    assert_eq!(layout.base_page(), 2);
+
+    // 32bpp utiles are 4x4, so levels of 16 pixels or less across are LT.
+    let lt: Vec<_> = layout
+        .levels
+        .iter()
+        .map(|level| level.is_lt_format)
+        .collect();
+    assert_eq!(lt, [false, false, true, true, true, true, true]);
+}
+
+#[test]
+fn vc4_image_mip_chain_layout_npot() {
+    // Levels past the base one are halvings of the next power of two size.
+    let layout = MipChainLayout::new((100, 60).into(), 16, 6);
+    assert_chain_rules(&layout);
+    let sizes: Vec<_> = layout.levels.iter().map(|level| level.size).collect();
+    assert_eq!(
+        sizes,
+        [
+            UVec2::new(100, 60),
+            UVec2::new(64, 32),
+            UVec2::new(32, 16),
+            UVec2::new(16, 8),
+            UVec2::new(8, 4),
+            UVec2::new(4, 2),
+        ]
+    );
+    // 16bpp utiles are 8x4, so LT starts at 32 pixels across or 16 down.
+    let lt: Vec<_> = layout
+        .levels
+        .iter()
+        .map(|level| level.is_lt_format)
+        .collect();
+    assert_eq!(lt, [false, false, true, true, true, true]);
+    for level in &layout.levels {
+        assert_eq!(level.alloc_size, Translator::alloc_size(level.size, 16));
+    }
+
+    // Narrow chains keep clamping the short side to 1.
+    let layout = MipChainLayout::new((256, 4).into(), 8, 3);
+    assert_chain_rules(&layout);
+    let sizes: Vec<_> = layout.levels.iter().map(|level| level.size).collect();
+    assert_eq!(
+        sizes,
+        [UVec2::new(256, 4), UVec2::new(128, 2), UVec2::new(64, 1)]
+    );
+}
+
+#[test]
+fn vc4_image_mip_chain_layout_single_level() {
+    let layout = MipChainLayout::new((1, 1).into(), 32, 1);
+    assert_chain_rules(&layout);
+    assert_eq!(layout.levels[0].offset, 0);
+    assert_eq!(layout.total_size, 64);
+    assert_eq!(layout.base_page(), 0);
+
+    let layout = MipChainLayout::new((640, 480).into(), 32, 1);
+    assert_eq!(
+        layout.total_size,
+        Translator::alloc_size((640, 480).into(), 32)
+    );
+}
+
+#[test]
+fn vc4_image_mip_chain_layout_etc1() {
+    let layout = MipChainLayout::new_etc1((256, 256).into(), 9);
+    assert_chain_rules(&layout);
+    // Levels are grids of 64bpp blocks, whose 2x4 utiles make a 16x16 grid
+    // of blocks LT already, and levels below 4x4 still take a whole utile.
+    let alloc_sizes: Vec<_> = layout.levels.iter().map(|level| level.alloc_size).collect();
+    assert_eq!(alloc_sizes, [32768, 8192, 2048, 512, 128, 64, 64, 64, 64]);
+    let lt: Vec<_> = layout
+        .levels
+        .iter()
+        .map(|level| level.is_lt_format)
+        .collect();
+    assert_eq!(lt, [false, false, true, true, true, true, true, true, true]);
+    assert_eq!(layout.levels[8].size, UVec2::new(1, 1));
+}
+
+#[test]
+fn vc4_image_try_new() {
+    for bpp in ALL_BPP {
+        let (translator, alloc_size) =
+            Translator::try_new_with_alloc_size((37, 5).into(), bpp).unwrap();
+        assert_eq!(alloc_size, Translator::alloc_size((37, 5).into(), bpp));
+        assert!(translator.is_lt_format());
+    }
+    for bpp in [0, 2, 24, 128] {
+        assert_eq!(
+            Translator::try_new((16, 16).into(), bpp).err(),
+            Some(TranslatorError::UnsupportedBpp(bpp))
+        );
+    }
+    for size in [UVec2::new(0, 16), UVec2::new(16, 0)] {
+        assert_eq!(
+            Translator::try_new(size, 32).err(),
+            Some(TranslatorError::ZeroSize(size))
+        );
+    }
+
+    // 65535x65535 at 32bpp takes 16 GiB, while 1024x1023 tiles of 32x32
+    // pixels just fit.
+    let size = UVec2::new(65535, 65535);
+    assert_eq!(
+        Translator::try_new(size, 32).err(),
+        Some(TranslatorError::TooLarge(size))
+    );
+    assert_eq!(
+        MipChainLayout::try_new(size, 32, 1).err(),
+        Some(TranslatorError::TooLarge(size))
+    );
+    let size = UVec2::new(32768, 32736);
+    assert!(Translator::try_new(size, 32).is_ok());
+    assert!(Translator::try_new(size + UVec2::Y, 32).is_err());
+    // The base level fits but leaves no room for the mips.
+    assert!(MipChainLayout::try_new(size, 32, 1).is_ok());
+    assert_eq!(
+        MipChainLayout::try_new(size, 32, 2).err(),
+        Some(TranslatorError::TooLarge(size))
+    );
+
+    assert_eq!(
+        MipChainLayout::try_new_etc1((0, 4).into(), 1).err(),
+        Some(TranslatorError::ZeroSize(UVec2::new(0, 1)))
+    );
+    assert_eq!(
+        MipChainLayout::try_new_etc1((64, 64).into(), 7).unwrap(),
+        MipChainLayout::new_etc1((64, 64).into(), 7)
+    );
+}
+
+#[test]
+fn vc4_image_try_coordinate_to_tile_address() {
+    for size in bulk_test_sizes() {
+        let translator = Translator::new(size, 16);
+        let last = size - UVec2::ONE;
+        let address = translator.try_coordinate_to_tile_address(last).unwrap();
+        assert_eq!(
+            address.offset,
+            translator.coordinate_to_tile_address(last).offset
+        );
+        for coord in [UVec2::new(size.x, 0), UVec2::new(0, size.y), size] {
+            assert_eq!(
+                translator.try_coordinate_to_tile_address(coord).err(),
+                Some(TranslatorError::OutOfBounds {
+                    coord,
+                    image_size: size
+                })
+            );
+        }
+    }
+}
+
+/// Utile size in pixels for each bpp, from the hardware docs.
+fn reference_utile_size(bpp: u32) -> UVec2 {
+    match bpp {
+        64 => UVec2::new(2, 4),
+        32 => UVec2::new(4, 4),
+        16 => UVec2::new(8, 4),
+        8 => UVec2::new(8, 8),
+        4 => UVec2::new(16, 8),
+        1 => UVec2::new(32, 16),
+        _ => unreachable!(),
+    }
+}
+
+fn reference_is_lt(size: UVec2, bpp: u32) -> bool {
+    let utile = reference_utile_size(bpp);
+    size.x <= 4 * utile.x || size.y <= 4 * utile.y
+}
+
+fn reference_alloc_size(size: UVec2, bpp: u32) -> u32 {
+    let utile = reference_utile_size(bpp);
+    if reference_is_lt(size, bpp) {
+        size.x.div_ceil(utile.x) * size.y.div_ceil(utile.y) * 64
+    } else {
+        size.x.div_ceil(utile.x * 8) * size.y.div_ceil(utile.y * 8) * 4096
+    }
+}
+
+/// Bit offset of a pixel, written straight from the tiling description of
+/// the VideoCore IV 3D Architecture Reference Guide rather than from the
+/// translators.
+///
+/// Pixels are in raster order within 64-byte utiles. LT images are utiles
+/// in raster order. T images are rows of 4 KiB tiles, each made of 2x2
+/// subtiles of 4x4 utiles in raster order. Even tile rows run left to
+/// right, with subtiles in the order bottom left, top left, top right,
+/// bottom right. Odd rows run right to left, with tiles rotated by 180
+/// degrees.
+fn reference_bit_address(size: UVec2, bpp: u32, coord: UVec2) -> u64 {
+    let utile = reference_utile_size(bpp);
+    let pixel = coord % utile;
+    let pixel_bit = ((pixel.y * utile.x + pixel.x) * bpp) as u64;
+    let utile_coord = coord / utile;
+
+    let utile_offset = if reference_is_lt(size, bpp) {
+        let utiles_across = size.x.div_ceil(utile.x);
+        (utile_coord.y * utiles_across + utile_coord.x) * 64
+    } else {
+        let tiles_across = size.x.div_ceil(utile.x * 8);
+        let tile = utile_coord / 8;
+        let subtile = (utile_coord / 4) % 2;
+        let (tile_index, subtile_index) = if tile.y.is_multiple_of(2) {
+            let order = [[0, 3], [1, 2]];
+            (
+                tile.y * tiles_across + tile.x,
+                order[subtile.y as usize][subtile.x as usize],
+            )
+        } else {
+            let order = [[2, 1], [3, 0]];
+            (
+                tile.y * tiles_across + tiles_across - 1 - tile.x,
+                order[subtile.y as usize][subtile.x as usize],
+            )
+        };
+        let utile_in_subtile = utile_coord % 4;
+        tile_index * 4096
+            + subtile_index * 1024
+            + (utile_in_subtile.y * 4 + utile_in_subtile.x) * 64
+    };
+    utile_offset as u64 * 8 + pixel_bit
+}
+
+/// Sizes up to a little over the T threshold of 1bpp images, with powers
+/// of two well represented.
+fn any_size() -> impl Strategy<Value = UVec2> {
+    let side = || prop_oneof![1..=200_u32, (0..8_u32).prop_map(|shift| 1 << shift)];
+    (side(), side()).prop_map(|(x, y)| UVec2::new(x, y))
+}
+
+fn any_bpp() -> impl Strategy<Value = u32> {
+    prop::sample::select(ALL_BPP.to_vec())
+}
+
+proptest! {
+    #[test]
+    fn vc4_image_prop_bijection(size in any_size(), bpp in any_bpp()) {
+        let (translator, alloc_size) = Translator::new_with_alloc_size(size, bpp);
+
+        // Padding the image to whole utiles (LT) or tiles (T) fills the
+        // allocation exactly, without changing the tiling.
+        let block = if translator.is_lt_format() {
+            translator.utile_size()
+        } else {
+            translator.utile_size() * 8
+        };
+        let padded_size = (size + block - UVec2::ONE) / block * block;
+        let (padded, padded_alloc_size) = Translator::new_with_alloc_size(padded_size, bpp);
+        prop_assert_eq!(padded_alloc_size, alloc_size);
+        prop_assert_eq!(padded.is_lt_format(), translator.is_lt_format());
+
+        let mut seen = vec![false; (alloc_size as usize * 8) / bpp as usize];
+        for y in 0..padded_size.y {
+            for x in 0..padded_size.x {
+                let coord = UVec2::new(x, y);
+                let address = padded.coordinate_to_tile_address(coord);
+                prop_assert!(address.offset < alloc_size);
+                let bit = address.offset as usize * 8 + address.bit as usize;
+                prop_assert_eq!(bit % bpp as usize, 0);
+                prop_assert!(!seen[bit / bpp as usize], "{} maps to a used address", coord);
+                seen[bit / bpp as usize] = true;
+                prop_assert_eq!(padded.tile_address_to_coordinate(address), coord);
+
+                if x < size.x && y < size.y {
+                    let address = translator.coordinate_to_tile_address(coord);
+                    prop_assert_eq!(address.offset as usize * 8 + address.bit as usize, bit);
+                    prop_assert_eq!(translator.tile_address_to_coordinate(address), coord);
+                }
+            }
+        }
+        prop_assert!(seen.iter().all(|&seen| seen));
+    }
+
+    #[test]
+    fn vc4_image_prop_matches_reference(size in any_size(), bpp in any_bpp()) {
+        let (translator, alloc_size) = Translator::new_with_alloc_size(size, bpp);
+        prop_assert_eq!(translator.is_lt_format(), reference_is_lt(size, bpp));
+        prop_assert_eq!(alloc_size, reference_alloc_size(size, bpp));
+        prop_assert_eq!(translator.utile_size(), reference_utile_size(bpp));
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let coord = UVec2::new(x, y);
+                let address = translator.coordinate_to_tile_address(coord);
+                let bit = address.offset as u64 * 8 + address.bit as u64;
+                prop_assert_eq!(bit, reference_bit_address(size, bpp, coord), "at {}", coord);
+            }
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use crate::encode::to_rgba;
+use crate::image::{Image, PixelFormat};
+use crate::settings::TextureSettings;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use vc4_image_addr::glam::UVec2;
+
+/// Settings of an atlas, read from `atlas.toml` in its directory.
+///
+/// ```toml
+/// padding = 4
+///
+/// [texture]
+/// format = "rgba4444"
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AtlasSettings {
+    /// Pixels around each sprite filled with copies of its edges, so that
+    /// filtering and lower mips don't bleed neighbouring sprites in. Each
+    /// mip level halves it.
+    pub padding: u32,
+    /// Largest width and height the atlas may grow to.
+    pub max_size: u32,
+    /// Import settings of the atlas texture. Wraps default to `Clamp`.
+    pub texture: TextureSettings,
+}
+
+impl Default for AtlasSettings {
+    fn default() -> Self {
+        Self {
+            padding: 4,
+            max_size: 2048,
+            texture: TextureSettings::default(),
+        }
+    }
+}
+
+impl AtlasSettings {
+    pub fn manifest_path(atlas_dir: &Path) -> PathBuf {
+        atlas_dir.join("atlas.toml")
+    }
+
+    /// Reads the settings of `atlas_dir`, or returns the default settings
+    /// if it has no manifest.
+    pub fn read(atlas_dir: &Path) -> Result<Self, String> {
+        let path = Self::manifest_path(atlas_dir);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let err = |err: &dyn std::fmt::Display| format!("{}: {err}", path.display());
+        let text = fs::read_to_string(&path).map_err(|e| err(&e))?;
+        toml::from_str(&text).map_err(|e| err(&e))
+    }
+}
+
+/// Pixel rectangle of a sprite within its atlas, top-down like the PNGs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtlasSprite {
+    pub name: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtlasLayout {
+    pub size: UVec2,
+    pub sprites: Vec<AtlasSprite>,
+}
+
+/// Places sprites of the given names and sizes on shelves, in the smallest
+/// power of two atlas they fit in. Sprites keep `padding` pixels from each
+/// other and from the edges of the atlas.
+pub fn layout_atlas(
+    sprites: &[(String, UVec2)],
+    padding: u32,
+    max_size: u32,
+) -> Result<AtlasLayout, String> {
+    let cell = |size: UVec2| size + UVec2::splat(padding * 2);
+
+    // Tallest sprites first keeps the shelves full.
+    let mut order: Vec<usize> = (0..sprites.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (a, b) = (&sprites[a], &sprites[b]);
+        (b.1.y, b.1.x, &a.0).cmp(&(a.1.y, a.1.x, &b.0))
+    });
+
+    let try_size = |size: UVec2| -> Option<Vec<AtlasSprite>> {
+        let mut placed = Vec::with_capacity(sprites.len());
+        let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+        for &i in &order {
+            let (name, sprite_size) = &sprites[i];
+            let cell_size = cell(*sprite_size);
+            if x + cell_size.x > size.x {
+                (x, y, shelf_height) = (0, y + shelf_height, 0);
+            }
+            if x + cell_size.x > size.x || y + cell_size.y > size.y {
+                return None;
+            }
+            placed.push(AtlasSprite {
+                name: name.clone(),
+                x: x + padding,
+                y: y + padding,
+                width: sprite_size.x,
+                height: sprite_size.y,
+            });
+            x += cell_size.x;
+            shelf_height = u32::max(shelf_height, cell_size.y);
+        }
+        placed.sort_by(|a, b| a.name.cmp(&b.name));
+        Some(placed)
+    };
+
+    let mut sizes = Vec::new();
+    let mut width = 1;
+    while width <= max_size {
+        let mut height = 1;
+        while height <= max_size {
+            sizes.push(UVec2::new(width, height));
+            height *= 2;
+        }
+        width *= 2;
+    }
+    sizes.sort_by_key(|size| (size.x * size.y, u32::max(size.x, size.y), size.y));
+
+    sizes
+        .into_iter()
+        .find_map(|size| {
+            let sprites = try_size(size)?;
+            Some(AtlasLayout { size, sprites })
+        })
+        .ok_or_else(|| format!("sprites don't fit in a {max_size}x{max_size} atlas"))
+}
+
+/// PNGs of `atlas_dir` sorted by name, with their sprite names.
+pub fn sprite_paths(atlas_dir: &Path) -> Result<Vec<(String, PathBuf)>, String> {
+    let mut sprites = Vec::new();
+    crate::for_each_file_ext_in_dir(atlas_dir, "png", |png_path, _| {
+        let name = png_path.file_stem().unwrap().to_string_lossy().into_owned();
+        sprites.push((name, png_path));
+        Ok(())
+    })?;
+    if sprites.is_empty() {
+        return Err(format!("{}: atlas has no sprites", atlas_dir.display()));
+    }
+    sprites.sort();
+    Ok(sprites)
+}
+
+/// Reads only the size of a PNG, which is all the layout needs.
+fn png_size(png_path: &Path) -> Result<UVec2, String> {
+    let err = |err: &dyn std::fmt::Display| format!("{}: {err}", png_path.display());
+    let file = fs::File::open(png_path).map_err(|e| err(&e))?;
+    let reader = png::Decoder::new(BufReader::new(file))
+        .read_info()
+        .map_err(|e| err(&e))?;
+    let info = reader.info();
+    Ok(UVec2::new(info.width, info.height))
+}
+
+/// Lays out the sprites of `atlas_dir` without decoding them.
+pub fn read_atlas_layout(
+    atlas_dir: &Path,
+    settings: &AtlasSettings,
+) -> Result<AtlasLayout, String> {
+    let mut sizes = Vec::new();
+    for (name, png_path) in sprite_paths(atlas_dir)? {
+        sizes.push((name, png_size(&png_path)?));
+    }
+    layout_atlas(&sizes, settings.padding, settings.max_size)
+        .map_err(|err| format!("{}: {err}", atlas_dir.display()))
+}
+
+/// Copies each sprite to its place in an RGBA atlas, extruding its edges
+/// into the padding around it.
+pub fn build_atlas_image(sprites: &[(String, Image)], layout: &AtlasLayout, padding: u32) -> Image {
+    let mut atlas = Image::new(layout.size, PixelFormat::Rgba);
+    for placed in &layout.sprites {
+        let (_, sprite) = sprites
+            .iter()
+            .find(|(name, _)| *name == placed.name)
+            .unwrap();
+        let pad = padding as i32;
+        for y in -pad..placed.height as i32 + pad {
+            for x in -pad..placed.width as i32 + pad {
+                let sx = x.clamp(0, placed.width as i32 - 1) as u32;
+                let sy = y.clamp(0, placed.height as i32 - 1) as u32;
+                let rgba = to_rgba(sprite.format, sprite.pixel(sx, sy));
+                let (ax, ay) = (placed.x as i32 + x, placed.y as i32 + y);
+                atlas.pixel_mut(ax as u32, ay as u32).copy_from_slice(&rgba);
+            }
+        }
+    }
+    atlas
+}
+
+/// Turns a file name into an identifier made of `[a-z0-9_]`.
+fn identifier(name: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if !ident.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        ident.insert(0, '_');
+    }
+    ident
+}
+
+/// Fails if two of `paths` get the same identifier, which would declare
+/// the same item twice in the generated module.
+fn check_identifiers(paths: impl IntoIterator<Item = (String, PathBuf)>) -> Result<(), String> {
+    let mut seen = HashMap::new();
+    for (ident, path) in paths {
+        if let Some(other) = seen.insert(ident.clone(), path.clone()) {
+            return Err(format!(
+                "{} and {} both map to the identifier `{ident}`",
+                other.display(),
+                path.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Source of a module declaring the UV rectangles of every sprite of every
+/// atlas, given the atlas directories, packed textures and layouts.
+pub fn atlas_module_source(atlases: &[(PathBuf, PathBuf, AtlasLayout)]) -> Result<String, String> {
+    let atlas_name = |atlas_dir: &Path| {
+        atlas_dir
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned()
+    };
+    check_identifiers(
+        atlases
+            .iter()
+            .map(|(atlas_dir, _, _)| (identifier(&atlas_name(atlas_dir)), atlas_dir.clone())),
+    )?;
+    for (atlas_dir, _, layout) in atlases {
+        check_identifiers(layout.sprites.iter().map(|sprite| {
+            let png_path = atlas_dir.join(format!("{}.png", sprite.name));
+            (identifier(&sprite.name), png_path)
+        }))?;
+    }
+
+    let mut src = String::new();
+    src.push_str(
+        "// Generated by vc4-pack-textures from the `.atlas` directories, do not edit.\n\
+         \n\
+         /// Sprite of an atlas. `x` and `y` are the top-left corner in pixels, `u0`\n\
+         /// and `v0` the bottom-left corner in texture coordinates, where `v` goes up\n\
+         /// like in every packed texture.\n\
+         #[derive(Debug, Copy, Clone, PartialEq)]\n\
+         pub struct AtlasRect {\n    \
+             pub x: u16,\n    \
+             pub y: u16,\n    \
+             pub width: u16,\n    \
+             pub height: u16,\n    \
+             pub u0: f32,\n    \
+             pub v0: f32,\n    \
+             pub u1: f32,\n    \
+             pub v1: f32,\n\
+         }\n",
+    );
+    for (atlas_dir, ctx_path, layout) in atlases {
+        let size = layout.size.as_vec2();
+        writeln!(src).unwrap();
+        writeln!(src, "pub mod {} {{", identifier(&atlas_name(atlas_dir))).unwrap();
+        writeln!(src, "    use super::AtlasRect;").unwrap();
+        writeln!(src).unwrap();
+        writeln!(
+            src,
+            "    pub const CTX_PATH: &str = {:?};",
+            ctx_path.display().to_string()
+        )
+        .unwrap();
+        writeln!(src, "    pub const WIDTH: u16 = {};", layout.size.x).unwrap();
+        writeln!(src, "    pub const HEIGHT: u16 = {};", layout.size.y).unwrap();
+        for sprite in &layout.sprites {
+            let u0 = sprite.x as f32 / size.x;
+            let u1 = (sprite.x + sprite.width) as f32 / size.x;
+            let v0 = (layout.size.y - sprite.y - sprite.height) as f32 / size.y;
+            let v1 = (layout.size.y - sprite.y) as f32 / size.y;
+            writeln!(src).unwrap();
+            writeln!(
+                src,
+                "    pub const {}: AtlasRect = AtlasRect {{\n        \
+                     x: {},\n        \
+                     y: {},\n        \
+                     width: {},\n        \
+                     height: {},\n        \
+                     u0: {u0:?},\n        \
+                     v0: {v0:?},\n        \
+                     u1: {u1:?},\n        \
+                     v1: {v1:?},\n    \
+                 }};",
+                identifier(&sprite.name).to_ascii_uppercase(),
+                sprite.x,
+                sprite.y,
+                sprite.width,
+                sprite.height,
+            )
+            .unwrap();
+        }
+        writeln!(src, "}}").unwrap();
+    }
+    Ok(src)
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Bumped whenever the packer writes different data for the same inputs,
+/// so that every output is rebuilt. 2: ETC1 levels tiled as 64bpp blocks.
+pub const PACKER_VERSION: u32 = 2;
+
+/// 64-bit FNV-1a, stable across builds and platforms unlike `DefaultHasher`.
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl std::hash::Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Hash of everything an output is built from: the crate, packer and `.ctx`
+/// versions, the import settings, and the name and contents of each input
+/// file. Directories among `in_paths` are skipped, their files being inputs
+/// of their own.
+pub fn content_hash(in_paths: &[PathBuf], settings: &dyn std::fmt::Debug) -> Result<u64, String> {
+    use std::hash::Hasher;
+    let mut hasher = Fnv1a::default();
+    hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher.write_u32(PACKER_VERSION);
+    hasher.write_u16(vc4_ctx::VERSION);
+    hasher.write(format!("{settings:?}").as_bytes());
+    for path in in_paths {
+        if path.is_dir() {
+            continue;
+        }
+        let contents = fs::read(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        let name = path.file_name().unwrap_or_default();
+        hasher.write(name.as_encoded_bytes());
+        hasher.write_u64(contents.len() as u64);
+        hasher.write(&contents);
+    }
+    Ok(hasher.finish())
+}
+
+/// Content hash of each output of the generated directory, by file name,
+/// stored as `hash name` lines in `generated/cache.txt`.
+#[derive(Default, PartialEq, Eq)]
+pub struct CacheIndex {
+    pub hashes: BTreeMap<String, u64>,
+}
+
+impl CacheIndex {
+    pub fn path(generated_dir: &Path) -> PathBuf {
+        generated_dir.join("cache.txt")
+    }
+
+    /// Reads the index, treating a missing or corrupt one as empty so that
+    /// everything is rebuilt.
+    pub fn read(generated_dir: &Path) -> Self {
+        let Ok(text) = fs::read_to_string(Self::path(generated_dir)) else {
+            return Self::default();
+        };
+        let hashes = text
+            .lines()
+            .filter_map(|line| {
+                let (hash, name) = line.split_once(' ')?;
+                Some((name.to_string(), u64::from_str_radix(hash, 16).ok()?))
+            })
+            .collect();
+        Self { hashes }
+    }
+
+    /// Writes the index, unless it is unchanged.
+    pub fn write(&self, generated_dir: &Path) -> Result<(), String> {
+        if *self == Self::read(generated_dir) {
+            return Ok(());
+        }
+        let path = Self::path(generated_dir);
+        let text: String = self
+            .hashes
+            .iter()
+            .map(|(name, hash)| format!("{hash:016x} {name}\n"))
+            .collect();
+        fs::write(&path, text).map_err(|err| format!("{}: {err}", path.display()))
+    }
+}
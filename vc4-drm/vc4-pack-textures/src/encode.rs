@@ -0,0 +1,386 @@
+use crate::etc1::{self, Etc1Quality};
+use crate::image::{Image, PixelFormat};
+use serde::Deserialize;
+use vc4_image_addr::glam::UVec2;
+use vc4_image_addr::{MipChainLayout, Translator, TranslatorError, TranslatorTrait};
+
+/// Texel format written to the `.ctx` file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextureFormat {
+    Rgba8888,
+    Rgbx8888,
+    Rgba4444,
+    Rgba5551,
+    Rgb565,
+    Luminance,
+    LumAlpha,
+    /// 4x4 blocks of ETC1 compressed RGB, without alpha.
+    Etc1,
+}
+
+/// How colors are quantized for formats with fewer than 8 bits per channel.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dither {
+    /// Rounds each channel to the nearest representable value.
+    #[default]
+    None,
+    /// 4x4 Bayer matrix, stable under animation and compresses well.
+    Ordered,
+    /// Floyd-Steinberg error diffusion, the most accurate on gradients.
+    ErrorDiffusion,
+}
+
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+impl TextureFormat {
+    /// The format storing exactly the channels of `format`.
+    pub fn for_pixel_format(format: PixelFormat) -> Self {
+        match format {
+            PixelFormat::Luminance => TextureFormat::Luminance,
+            PixelFormat::LumAlpha => TextureFormat::LumAlpha,
+            PixelFormat::Rgb => TextureFormat::Rgbx8888,
+            PixelFormat::Rgba => TextureFormat::Rgba8888,
+        }
+    }
+
+    /// The format of a hardware `TextureDataType` value, if the packer
+    /// writes it.
+    pub fn from_data_type(data_type: u8) -> Option<Self> {
+        [
+            TextureFormat::Rgba8888,
+            TextureFormat::Rgbx8888,
+            TextureFormat::Rgba4444,
+            TextureFormat::Rgba5551,
+            TextureFormat::Rgb565,
+            TextureFormat::Luminance,
+            TextureFormat::LumAlpha,
+            TextureFormat::Etc1,
+        ]
+        .into_iter()
+        .find(|format| format.data_type() == data_type)
+    }
+
+    /// Channels of the image a level decodes to.
+    pub fn pixel_format(self) -> PixelFormat {
+        match self {
+            TextureFormat::Rgba8888 | TextureFormat::Rgba4444 | TextureFormat::Rgba5551 => {
+                PixelFormat::Rgba
+            }
+            TextureFormat::Rgbx8888 | TextureFormat::Rgb565 | TextureFormat::Etc1 => {
+                PixelFormat::Rgb
+            }
+            TextureFormat::Luminance => PixelFormat::Luminance,
+            TextureFormat::LumAlpha => PixelFormat::LumAlpha,
+        }
+    }
+
+    /// Hardware `TextureDataType` value.
+    pub fn data_type(self) -> u8 {
+        match self {
+            TextureFormat::Rgba8888 => 0,
+            TextureFormat::Rgbx8888 => 1,
+            TextureFormat::Rgba4444 => 2,
+            TextureFormat::Rgba5551 => 3,
+            TextureFormat::Rgb565 => 4,
+            TextureFormat::Luminance => 5,
+            TextureFormat::LumAlpha => 7,
+            TextureFormat::Etc1 => 8,
+        }
+    }
+
+    pub fn bpp(self) -> u32 {
+        match self {
+            TextureFormat::Rgba8888 | TextureFormat::Rgbx8888 => 32,
+            TextureFormat::Rgba4444
+            | TextureFormat::Rgba5551
+            | TextureFormat::Rgb565
+            | TextureFormat::LumAlpha => 16,
+            TextureFormat::Luminance => 8,
+            TextureFormat::Etc1 => 4,
+        }
+    }
+
+    /// Layout of a mip chain of `num_mips` levels, as packed into `.ctx`
+    /// files. ETC1 levels are tiled as a grid of 64bpp blocks. Fails for
+    /// sizes too large to tile, which only come from corrupt files.
+    pub fn mip_chain_layout(
+        self,
+        size: UVec2,
+        num_mips: u32,
+    ) -> Result<MipChainLayout, TranslatorError> {
+        match self {
+            TextureFormat::Etc1 => MipChainLayout::try_new_etc1(size, num_mips),
+            _ => MipChainLayout::try_new(size, self.bpp(), num_mips),
+        }
+    }
+
+    /// Whether the TMU must flip rows within each 4x4 block. Blocks are
+    /// encoded top-down like any ETC1 image, while levels are stored
+    /// bottom-up.
+    pub fn etc_flip(self) -> bool {
+        self == TextureFormat::Etc1
+    }
+
+    /// Bits stored for each of the R, G, B and A channels.
+    fn channel_bits(self) -> [u32; 4] {
+        match self {
+            TextureFormat::Rgba4444 => [4, 4, 4, 4],
+            TextureFormat::Rgba5551 => [5, 5, 5, 1],
+            TextureFormat::Rgb565 => [5, 6, 5, 8],
+            _ => [8, 8, 8, 8],
+        }
+    }
+
+    /// Writes one texel in the byte order the TMU expects. 32bpp texels
+    /// are BGRA, matching the framebuffer format. 16bpp texels are little
+    /// endian words with red in the top bits, which the TMU returns in the
+    /// same channel order as the 32bpp ones.
+    fn encode_texel(self, rgba: [u8; 4], out: &mut [u8]) {
+        let [r, g, b, a] = rgba;
+        let [rq, gq, bq, aq] = quantize(rgba, self.channel_bits());
+        match self {
+            TextureFormat::Rgba8888 => out.copy_from_slice(&[b, g, r, a]),
+            TextureFormat::Rgbx8888 => out.copy_from_slice(&[b, g, r, 0xff]),
+            TextureFormat::Rgba4444 => {
+                out.copy_from_slice(&(rq << 12 | gq << 8 | bq << 4 | aq).to_le_bytes())
+            }
+            TextureFormat::Rgba5551 => {
+                out.copy_from_slice(&(rq << 11 | gq << 6 | bq << 1 | aq).to_le_bytes())
+            }
+            TextureFormat::Rgb565 => out.copy_from_slice(&(rq << 11 | gq << 5 | bq).to_le_bytes()),
+            TextureFormat::Luminance => out[0] = r,
+            TextureFormat::LumAlpha => out.copy_from_slice(&[r, a]),
+            TextureFormat::Etc1 => unreachable!("ETC1 is encoded in blocks"),
+        }
+    }
+
+    /// Reads one texel written by `encode_texel`, in the channels of
+    /// `pixel_format`.
+    fn decode_texel(self, texel: &[u8], out: &mut [u8]) {
+        let word = || u16::from_le_bytes([texel[0], texel[1]]);
+        let rgba = match self {
+            TextureFormat::Rgba8888 | TextureFormat::Rgbx8888 => {
+                [texel[2], texel[1], texel[0], texel[3]]
+            }
+            TextureFormat::Rgba4444 => {
+                let w = word();
+                dequantize([w >> 12, w >> 8 & 0xf, w >> 4 & 0xf, w & 0xf], [4, 4, 4, 4])
+            }
+            TextureFormat::Rgba5551 => {
+                let w = word();
+                dequantize([w >> 11, w >> 6 & 0x1f, w >> 1 & 0x1f, w & 1], [5, 5, 5, 1])
+            }
+            TextureFormat::Rgb565 => {
+                let w = word();
+                dequantize([w >> 11, w >> 5 & 0x3f, w & 0x1f, 0xff], [5, 6, 5, 8])
+            }
+            TextureFormat::Luminance => [texel[0], texel[0], texel[0], 0xff],
+            TextureFormat::LumAlpha => [texel[0], texel[0], texel[0], texel[1]],
+            TextureFormat::Etc1 => unreachable!("ETC1 is decoded in blocks"),
+        };
+        match self.pixel_format() {
+            PixelFormat::Luminance => out[0] = rgba[0],
+            PixelFormat::LumAlpha => out.copy_from_slice(&[rgba[0], rgba[3]]),
+            PixelFormat::Rgb => out.copy_from_slice(&rgba[0..3]),
+            PixelFormat::Rgba => out.copy_from_slice(&rgba),
+        }
+    }
+}
+
+pub fn to_rgba(format: PixelFormat, pixel: &[u8]) -> [u8; 4] {
+    match format {
+        PixelFormat::Luminance => [pixel[0], pixel[0], pixel[0], 0xff],
+        PixelFormat::LumAlpha => [pixel[0], pixel[0], pixel[0], pixel[1]],
+        PixelFormat::Rgb => [pixel[0], pixel[1], pixel[2], 0xff],
+        PixelFormat::Rgba => [pixel[0], pixel[1], pixel[2], pixel[3]],
+    }
+}
+
+/// Rounds 8-bit channels to `bits` bits each.
+fn quantize(rgba: [u8; 4], bits: [u32; 4]) -> [u16; 4] {
+    let mut q = [0_u16; 4];
+    for c in 0..4 {
+        let max = (1_u32 << bits[c]) - 1;
+        q[c] = ((rgba[c] as u32 * max + 127) / 255) as u16;
+    }
+    q
+}
+
+/// Widens channels of `bits` bits each back to 8 bits.
+fn dequantize(q: [u16; 4], bits: [u32; 4]) -> [u8; 4] {
+    let mut rgba = [0_u8; 4];
+    for c in 0..4 {
+        let max = (1_u32 << bits[c]) - 1;
+        rgba[c] = ((q[c] as u32 * 255 + max / 2) / max) as u8;
+    }
+    rgba
+}
+
+/// Maps a channel value to the nearest 8-bit value representable with
+/// `bits` bits, so that `quantize` returns it exactly.
+fn quantize_channel(v: f32, bits: u32) -> u8 {
+    let max = ((1_u32 << bits) - 1) as f32;
+    let q = (v.clamp(0.0, 255.0) * max / 255.0).round();
+    (q * 255.0 / max).round() as u8
+}
+
+/// Converts `image` to top-down RGBA pixels quantized to the channel depths
+/// of `format`. Only color channels are dithered, alpha is rounded so that
+/// alpha tested edges stay clean.
+fn dither_pixels(image: &Image, format: TextureFormat, dither: Dither) -> Vec<[u8; 4]> {
+    let (width, height) = (image.size.x as usize, image.size.y as usize);
+    let bits = format.channel_bits();
+    let mut pixels: Vec<[f32; 4]> = (0..image.size.y)
+        .flat_map(|y| (0..image.size.x).map(move |x| (x, y)))
+        .map(|(x, y)| to_rgba(image.format, image.pixel(x, y)).map(|c| c as f32))
+        .collect();
+    if bits == [8; 4] {
+        return pixels.iter().map(|p| p.map(|c| c as u8)).collect();
+    }
+
+    let mut out = vec![[0_u8; 4]; pixels.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            for c in 0..4 {
+                let v = pixels[i][c];
+                if bits[c] == 8 || c == 3 {
+                    out[i][c] = quantize_channel(v, bits[c]);
+                    continue;
+                }
+
+                let step = 255.0 / ((1_u32 << bits[c]) - 1) as f32;
+                match dither {
+                    Dither::None => out[i][c] = quantize_channel(v, bits[c]),
+                    Dither::Ordered => {
+                        let threshold = (BAYER_4X4[y % 4][x % 4] as f32 + 0.5) / 16.0 - 0.5;
+                        out[i][c] = quantize_channel(v + threshold * step, bits[c]);
+                    }
+                    Dither::ErrorDiffusion => {
+                        out[i][c] = quantize_channel(v, bits[c]);
+                        let error = v - out[i][c] as f32;
+                        let mut spread = |dx: isize, dy: usize, weight: f32| {
+                            let (nx, ny) = (x as isize + dx, y + dy);
+                            if nx >= 0 && (nx as usize) < width && ny < height {
+                                pixels[ny * width + nx as usize][c] += error * weight;
+                            }
+                        };
+                        spread(1, 0, 7.0 / 16.0);
+                        spread(-1, 1, 3.0 / 16.0);
+                        spread(0, 1, 5.0 / 16.0);
+                        spread(1, 1, 1.0 / 16.0);
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+fn size_in_etc1_blocks(size: UVec2) -> UVec2 {
+    (size + UVec2::splat(3)) / 4
+}
+
+/// Encodes and tiles the blocks of an ETC1 level. Block rows are stored
+/// bottom-up and aligned to the bottom of the image, so that with
+/// `etc_flip` the TMU sees the same rows as for uncompressed levels. Any
+/// padding ends up past the top of the image.
+fn tile_etc1_level(image: &Image, quality: Etc1Quality, out: &mut [u8]) {
+    let size_in_blocks = size_in_etc1_blocks(image.size);
+    let translator = Translator::new(size_in_blocks, 64);
+    for block_y in 0..size_in_blocks.y {
+        let top = image.size.y as i32 - 4 * (block_y as i32 + 1);
+        for block_x in 0..size_in_blocks.x {
+            let mut block = [[0_u8; 3]; 16];
+            let mut mask = 0_u16;
+            for y in 0..4 {
+                for x in 0..4 {
+                    let (px, py) = (block_x * 4 + x, top + y as i32);
+                    if px < image.size.x && py >= 0 {
+                        let [r, g, b, _] = to_rgba(image.format, image.pixel(px, py as u32));
+                        block[(y * 4 + x) as usize] = [r, g, b];
+                        mask |= 1 << (y * 4 + x);
+                    }
+                }
+            }
+
+            let offset = translator
+                .coordinate_to_tile_address(UVec2::new(block_x, block_y))
+                .offset as usize;
+            out[offset..offset + 8].copy_from_slice(&etc1::encode_block(&block, mask, quality));
+        }
+    }
+}
+
+/// Tiles `image` into `out`, flipping it so that the first row in memory is
+/// the bottom of the image.
+pub fn tile_level(
+    image: &Image,
+    format: TextureFormat,
+    dither: Dither,
+    etc1_quality: Etc1Quality,
+    out: &mut [u8],
+) {
+    if format == TextureFormat::Etc1 {
+        tile_etc1_level(image, etc1_quality, out);
+        return;
+    }
+
+    let pixels = dither_pixels(image, format, dither);
+    let texel_size = (format.bpp() / 8) as usize;
+    let stride = image.size.x as usize * texel_size;
+    let mut linear = vec![0_u8; stride * image.size.y as usize];
+    for (row, linear_row) in pixels
+        .chunks_exact(image.size.x as usize)
+        .rev()
+        .zip(linear.chunks_exact_mut(stride))
+    {
+        for (&rgba, texel) in row.iter().zip(linear_row.chunks_exact_mut(texel_size)) {
+            format.encode_texel(rgba, texel);
+        }
+    }
+    Translator::new(image.size, format.bpp()).tile(&linear, stride, out);
+}
+
+/// Decodes the tiled level `tiled` of `size` texels, the inverse of
+/// `tile_level`: rows come back top-down and texels are widened back to 8
+/// bits per channel.
+pub fn untile_level(tiled: &[u8], size: UVec2, format: TextureFormat) -> Image {
+    let mut image = Image::new(size, format.pixel_format());
+    if format == TextureFormat::Etc1 {
+        let size_in_blocks = size_in_etc1_blocks(size);
+        let stride = size_in_blocks.x as usize * 8;
+        let mut blocks = vec![0_u8; stride * size_in_blocks.y as usize];
+        Translator::new(size_in_blocks, 64).untile(tiled, &mut blocks, stride);
+        for (block_y, block_row) in blocks.chunks_exact(stride).enumerate() {
+            let top = size.y as i32 - 4 * (block_y as i32 + 1);
+            for (block_x, block) in block_row.chunks_exact(8).enumerate() {
+                let pixels = etc1::decode_block(block.try_into().unwrap());
+                for y in 0..4 {
+                    for x in 0..4 {
+                        let (px, py) = (block_x as u32 * 4 + x, top + y as i32);
+                        if px < size.x && py >= 0 {
+                            let pixel = pixels[(y * 4 + x) as usize];
+                            image.pixel_mut(px, py as u32).copy_from_slice(&pixel);
+                        }
+                    }
+                }
+            }
+        }
+        return image;
+    }
+
+    let texel_size = (format.bpp() / 8) as usize;
+    let stride = size.x as usize * texel_size;
+    let mut linear = vec![0_u8; stride * size.y as usize];
+    Translator::new(size, format.bpp()).untile(tiled, &mut linear, stride);
+    // Rows come back bottom-up.
+    for (y, row) in linear.chunks_exact(stride).rev().enumerate() {
+        for (x, texel) in row.chunks_exact(texel_size).enumerate() {
+            format.decode_texel(texel, image.pixel_mut(x as u32, y as u32));
+        }
+    }
+    image
+}
@@ -0,0 +1,337 @@
+//! ETC1 block encoder.
+//!
+//! A block holds 4x4 pixels as two 2x4 or 4x2 sub-blocks, each with a base
+//! color and one of eight intensity modifier tables. Base colors are either
+//! two 4-bit colors ("individual" mode) or a 5-bit color and a 3-bit signed
+//! delta ("differential" mode). Each pixel picks one of four modifiers of
+//! its sub-block's table.
+
+use serde::Deserialize;
+
+/// How hard the encoder searches for base colors.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Etc1Quality {
+    /// Only the quantized average color of each sub-block.
+    Fast,
+    /// The average color moved along the gray axis, which the modifier
+    /// tables can't reach by themselves.
+    #[default]
+    Medium,
+    /// Every neighbour of the average color, and larger moves along the
+    /// gray axis.
+    High,
+}
+
+const MODIFIER_TABLES: [[i32; 2]; 8] = [
+    [2, 8],
+    [5, 17],
+    [9, 29],
+    [13, 42],
+    [18, 60],
+    [24, 80],
+    [33, 106],
+    [47, 183],
+];
+
+/// Squared error weights of the R, G and B channels, following their
+/// contribution to luminance.
+const WEIGHTS: [u32; 3] = [299, 587, 114];
+
+/// Modifier of a 2-bit pixel index, whose high bit is the sign.
+fn modifier(table: usize, index: usize) -> i32 {
+    let value = MODIFIER_TABLES[table][index & 1];
+    if index & 2 != 0 {
+        -value
+    } else {
+        value
+    }
+}
+
+fn weighted_error(a: [i32; 3], b: [u8; 3]) -> u32 {
+    (0..3)
+        .map(|c| {
+            let d = a[c] - b[c] as i32;
+            WEIGHTS[c] * (d * d) as u32
+        })
+        .sum()
+}
+
+/// Expands a quantized channel to 8 bits the way the decoder does.
+fn expand(value: i32, bits: u32) -> i32 {
+    (value << (8 - bits)) | (value >> (2 * bits - 8))
+}
+
+/// Pixels of one sub-block, with their index in the block (`x * 4 + y`).
+struct SubBlock {
+    pixels: [[u8; 3]; 8],
+    indices: [usize; 8],
+    len: usize,
+}
+
+impl SubBlock {
+    fn new(block: &[[u8; 3]; 16], mask: u16, flip: bool, second: bool) -> Self {
+        let mut sub_block = SubBlock {
+            pixels: [[0; 3]; 8],
+            indices: [0; 8],
+            len: 0,
+        };
+        for y in 0..4 {
+            for x in 0..4 {
+                let in_second = if flip { y >= 2 } else { x >= 2 };
+                let index = x * 4 + y;
+                if in_second == second && mask & (1 << index) != 0 {
+                    sub_block.pixels[sub_block.len] = block[y * 4 + x];
+                    sub_block.indices[sub_block.len] = index;
+                    sub_block.len += 1;
+                }
+            }
+        }
+        sub_block
+    }
+
+    fn pixels(&self) -> impl Iterator<Item = (&[u8; 3], usize)> {
+        self.pixels[..self.len]
+            .iter()
+            .zip(self.indices[..self.len].iter().copied())
+    }
+
+    /// Average color, quantized to `bits` bits per channel.
+    fn quantized_average(&self, bits: u32) -> [i32; 3] {
+        let max = (1 << bits) - 1;
+        let mut sum = [0_u32; 3];
+        for (pixel, _) in self.pixels() {
+            for c in 0..3 {
+                sum[c] += pixel[c] as u32;
+            }
+        }
+        let count = u32::max(self.len as u32, 1);
+        sum.map(|s| ((s as f32 / count as f32) * max as f32 / 255.0).round() as i32)
+    }
+}
+
+/// Encoding of one sub-block for a given base color.
+#[derive(Copy, Clone)]
+struct Fit {
+    color: [i32; 3],
+    table: usize,
+    /// 2-bit modifier index of each pixel of the block.
+    indices: [u8; 16],
+    error: u32,
+}
+
+/// Finds the best table and pixel indices for the quantized base `color`.
+fn fit(sub_block: &SubBlock, color: [i32; 3], bits: u32) -> Fit {
+    let base = color.map(|c| expand(c, bits));
+    let mut best = Fit {
+        color,
+        table: 0,
+        indices: [0; 16],
+        error: u32::MAX,
+    };
+    for table in 0..8 {
+        let mut indices = [0_u8; 16];
+        let mut error = 0;
+        for (pixel, index) in sub_block.pixels() {
+            let (modifier_index, pixel_error) = (0..4)
+                .map(|i| {
+                    let m = modifier(table, i);
+                    let decoded = base.map(|c| (c + m).clamp(0, 255));
+                    (i, weighted_error(decoded, *pixel))
+                })
+                .min_by_key(|&(_, e)| e)
+                .unwrap();
+            indices[index] = modifier_index as u8;
+            error += pixel_error;
+            if error >= best.error {
+                break;
+            }
+        }
+        if error < best.error {
+            best = Fit {
+                color,
+                table,
+                indices,
+                error,
+            };
+        }
+    }
+    best
+}
+
+/// Base colors to try around the quantized average `center`.
+fn candidates(center: [i32; 3], bits: u32, quality: Etc1Quality) -> Vec<[i32; 3]> {
+    let max = (1 << bits) - 1;
+    let mut colors = Vec::new();
+    let mut push = |color: [i32; 3]| {
+        let color = color.map(|c| c.clamp(0, max));
+        if !colors.contains(&color) {
+            colors.push(color);
+        }
+    };
+
+    push(center);
+    let gray_steps: &[i32] = match quality {
+        Etc1Quality::Fast => &[],
+        Etc1Quality::Medium => &[-3, -2, -1, 1, 2, 3],
+        Etc1Quality::High => &[-4, -3, -2, 2, 3, 4],
+    };
+    for step in gray_steps {
+        push(center.map(|c| c + step));
+    }
+    if quality == Etc1Quality::High {
+        for dr in -1..=1 {
+            for dg in -1..=1 {
+                for db in -1..=1 {
+                    push([center[0] + dr, center[1] + dg, center[2] + db]);
+                }
+            }
+        }
+    }
+    colors
+}
+
+/// Best fits of each candidate, sorted by error.
+fn fits(sub_block: &SubBlock, bits: u32, quality: Etc1Quality) -> Vec<Fit> {
+    let center = sub_block.quantized_average(bits);
+    let mut fits: Vec<Fit> = candidates(center, bits, quality)
+        .into_iter()
+        .map(|color| fit(sub_block, color, bits))
+        .collect();
+    fits.sort_by_key(|f| f.error);
+    fits
+}
+
+struct Encoding {
+    differential: bool,
+    flip: bool,
+    fits: [Fit; 2],
+}
+
+impl Encoding {
+    fn error(&self) -> u32 {
+        self.fits[0].error.saturating_add(self.fits[1].error)
+    }
+
+    fn to_bytes(&self) -> [u8; 8] {
+        let [first, second] = &self.fits;
+        let mut bits = 0_u64;
+        if self.differential {
+            for c in 0..3 {
+                let delta = (second.color[c] - first.color[c]) as u64 & 0x7;
+                bits |= ((first.color[c] as u64) << 3 | delta) << (56 - c * 8);
+            }
+        } else {
+            for c in 0..3 {
+                bits |= ((first.color[c] as u64) << 4 | second.color[c] as u64) << (56 - c * 8);
+            }
+        }
+        bits |= (first.table as u64) << 37 | (second.table as u64) << 34;
+        bits |= (self.differential as u64) << 33 | (self.flip as u64) << 32;
+        for i in 0..16 {
+            let index = first.indices[i] | second.indices[i];
+            bits |= ((index as u64 >> 1) << (16 + i)) | ((index as u64 & 1) << i);
+        }
+        bits.to_be_bytes()
+    }
+}
+
+/// Encodes the 4x4 RGB `block` (row-major, top-down) into an ETC1 block
+/// in the spec's big-endian byte order. Pixels whose bit isn't set in
+/// `mask` (indexed `y * 4 + x`) lie outside the image and are ignored.
+pub fn encode_block(block: &[[u8; 3]; 16], mask: u16, quality: Etc1Quality) -> [u8; 8] {
+    // Convert the row-major mask to the block's column-major pixel order.
+    let mut column_mask = 0_u16;
+    for y in 0..4 {
+        for x in 0..4 {
+            if mask & (1 << (y * 4 + x)) != 0 {
+                column_mask |= 1 << (x * 4 + y);
+            }
+        }
+    }
+
+    let mut best: Option<Encoding> = None;
+    for flip in [false, true] {
+        let sub_blocks = [
+            SubBlock::new(block, column_mask, flip, false),
+            SubBlock::new(block, column_mask, flip, true),
+        ];
+
+        let individual = sub_blocks
+            .each_ref()
+            .map(|sub_block| fits(sub_block, 4, quality)[0]);
+        let encodings = [Some(Encoding {
+            differential: false,
+            flip,
+            fits: individual,
+        })]
+        .into_iter()
+        .chain([differential(&sub_blocks, flip, quality)]);
+
+        for encoding in encodings.flatten() {
+            if best.as_ref().is_none_or(|b| encoding.error() < b.error()) {
+                best = Some(encoding);
+            }
+        }
+    }
+    best.unwrap().to_bytes()
+}
+
+/// Best differential encoding, whose base colors must be within the 3-bit
+/// delta range of each other.
+fn differential(sub_blocks: &[SubBlock; 2], flip: bool, quality: Etc1Quality) -> Option<Encoding> {
+    let first_fits = fits(&sub_blocks[0], 5, quality);
+    let second_fits = fits(&sub_blocks[1], 5, quality);
+    let in_range = |a: &Fit, b: &Fit| (0..3).all(|c| (-4..=3).contains(&(b.color[c] - a.color[c])));
+
+    let mut best: Option<Encoding> = None;
+    for first in &first_fits {
+        for second in &second_fits {
+            if !in_range(first, second) {
+                continue;
+            }
+            let encoding = Encoding {
+                differential: true,
+                flip,
+                fits: [*first, *second],
+            };
+            if best.as_ref().is_none_or(|b| encoding.error() < b.error()) {
+                best = Some(encoding);
+            }
+        }
+    }
+    best
+}
+
+/// Decodes an ETC1 block in the spec's byte order into 4x4 RGB pixels
+/// (row-major, top-down), the inverse of [`encode_block`].
+pub fn decode_block(block: [u8; 8]) -> [[u8; 3]; 16] {
+    let bits = u64::from_be_bytes(block);
+    let differential = bits >> 33 & 1 != 0;
+    let flip = bits >> 32 & 1 != 0;
+
+    let channels: [[i32; 2]; 3] = std::array::from_fn(|c| {
+        let byte = (bits >> (56 - c * 8)) as i32 & 0xff;
+        if differential {
+            let first = byte >> 3;
+            let delta = ((byte & 0x7) << 29) >> 29;
+            [expand(first, 5), expand(first + delta, 5)]
+        } else {
+            [expand(byte >> 4, 4), expand(byte & 0xf, 4)]
+        }
+    });
+    let bases = [channels.map(|c| c[0]), channels.map(|c| c[1])];
+    let tables = [(bits >> 37 & 0x7) as usize, (bits >> 34 & 0x7) as usize];
+
+    let mut pixels = [[0_u8; 3]; 16];
+    for y in 0..4 {
+        for x in 0..4 {
+            let i = x * 4 + y;
+            let sub_block = if flip { y >= 2 } else { x >= 2 } as usize;
+            let index = (bits >> (16 + i) & 1) << 1 | (bits >> i & 1);
+            let m = modifier(tables[sub_block], index as usize);
+            pixels[y * 4 + x] = bases[sub_block].map(|c| (c + m).clamp(0, 255) as u8);
+        }
+    }
+    pixels
+}
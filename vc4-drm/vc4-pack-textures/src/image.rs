@@ -0,0 +1,129 @@
+use std::fs;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use vc4_image_addr::glam::UVec2;
+
+/// Channel layout of a decoded image, each channel being 8 bits.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PixelFormat {
+    Luminance,
+    LumAlpha,
+    Rgb,
+    Rgba,
+}
+
+impl PixelFormat {
+    /// Index of the alpha channel, if any.
+    pub fn alpha_channel(self) -> Option<usize> {
+        match self {
+            PixelFormat::LumAlpha => Some(1),
+            PixelFormat::Rgba => Some(3),
+            PixelFormat::Luminance | PixelFormat::Rgb => None,
+        }
+    }
+
+    pub fn channels(self) -> usize {
+        match self {
+            PixelFormat::Luminance => 1,
+            PixelFormat::LumAlpha => 2,
+            PixelFormat::Rgb => 3,
+            PixelFormat::Rgba => 4,
+        }
+    }
+}
+
+/// Linear 8-bit image with top-down rows, as stored in the PNG.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub size: UVec2,
+    pub format: PixelFormat,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(size: UVec2, format: PixelFormat) -> Self {
+        Self {
+            size,
+            format,
+            data: vec![0; (size.x * size.y) as usize * format.channels()],
+        }
+    }
+
+    /// Decodes any PNG color type and bit depth: palettes are expanded (with
+    /// their transparency), low bit depths widened and 16-bit channels
+    /// reduced to 8 bits.
+    pub fn read_png(png_path: &Path) -> Result<Self, String> {
+        let err = |err: &dyn std::fmt::Display| format!("{}: {err}", png_path.display());
+
+        let file = fs::File::open(png_path).map_err(|e| err(&e))?;
+        let mut decoder = png::Decoder::new(BufReader::new(file));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|e| err(&e))?;
+
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).map_err(|e| err(&e))?;
+        data.truncate(info.buffer_size());
+
+        let format = match info.color_type {
+            png::ColorType::Grayscale => PixelFormat::Luminance,
+            png::ColorType::GrayscaleAlpha => PixelFormat::LumAlpha,
+            png::ColorType::Rgb => PixelFormat::Rgb,
+            png::ColorType::Rgba => PixelFormat::Rgba,
+            png::ColorType::Indexed => return Err(err(&"palette was not expanded")),
+        };
+        let size = UVec2::new(info.width, info.height);
+        if size.x == 0 || size.y == 0 || size.x > 2048 || size.y > 2048 {
+            return Err(err(&format!(
+                "{}x{} is outside of the 1x1 to 2048x2048 texture limits",
+                size.x, size.y
+            )));
+        }
+        if info.line_size != size.x as usize * format.channels() {
+            return Err(err(&"unexpected row size after normalization"));
+        }
+
+        Ok(Self { size, format, data })
+    }
+
+    pub fn write_png(&self, png_path: &Path) -> Result<(), String> {
+        let err = |err: &dyn std::fmt::Display| format!("{}: {err}", png_path.display());
+
+        let file = fs::File::create(png_path).map_err(|e| err(&e))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.size.x, self.size.y);
+        encoder.set_color(match self.format {
+            PixelFormat::Luminance => png::ColorType::Grayscale,
+            PixelFormat::LumAlpha => png::ColorType::GrayscaleAlpha,
+            PixelFormat::Rgb => png::ColorType::Rgb,
+            PixelFormat::Rgba => png::ColorType::Rgba,
+        });
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| err(&e))?;
+        writer.write_image_data(&self.data).map_err(|e| err(&e))?;
+        writer.finish().map_err(|e| err(&e))
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> &[u8] {
+        let channels = self.format.channels();
+        let offset = (y * self.size.x + x) as usize * channels;
+        &self.data[offset..offset + channels]
+    }
+
+    pub fn pixel_mut(&mut self, x: u32, y: u32) -> &mut [u8] {
+        let channels = self.format.channels();
+        let offset = (y * self.size.x + x) as usize * channels;
+        &mut self.data[offset..offset + channels]
+    }
+
+    /// Multiplies the color channels by alpha.
+    pub fn premultiply_alpha(&mut self) {
+        let Some(alpha_channel) = self.format.alpha_channel() else {
+            return;
+        };
+        for pixel in self.data.chunks_exact_mut(self.format.channels()) {
+            let alpha = pixel[alpha_channel] as u32;
+            for v in &mut pixel[..alpha_channel] {
+                *v = ((*v as u32 * alpha + 127) / 255) as u8;
+            }
+        }
+    }
+}
@@ -1,23 +1,47 @@
-use flate2::write::ZlibEncoder;
-use smallvec::SmallVec;
+mod atlas;
+mod cache;
+mod encode;
+mod etc1;
+mod image;
+mod mips;
+mod normals;
+mod settings;
+
+pub use atlas::{build_atlas_image, layout_atlas, AtlasLayout, AtlasSettings, AtlasSprite};
+pub use cache::PACKER_VERSION;
+use cache::{content_hash, CacheIndex};
+use encode::tile_level;
+pub use encode::{untile_level, Dither, TextureFormat};
+pub use etc1::Etc1Quality;
+pub use image::{Image, PixelFormat};
+pub use mips::generate_mips;
+pub use normals::{normals_to_lum_alpha, prepare_normals};
+pub use settings::*;
 use std::fs;
-use std::io::{BufReader, Write};
-use std::path::PathBuf;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use vc4_ctx::{CtxHeader, CtxTiling};
 use vc4_image_addr::glam::*;
-use vc4_image_addr::{Translator, TranslatorTrait};
 
-fn for_each_file_ext_in_dir<F>(dir: &PathBuf, ext: &str, mut f: F) -> Result<(), String>
+/// Suffixes of the six PNGs making up a cube map, in hardware face order.
+pub const CUBE_FACE_SUFFIXES: [&str; 6] = ["_px", "_nx", "_py", "_ny", "_pz", "_nz"];
+
+fn for_each_file_ext_in_dir<F>(dir: &Path, ext: &str, mut f: F) -> Result<(), String>
 where
     F: FnMut(PathBuf, fs::Metadata) -> Result<(), String>,
 {
-    for path_ent in fs::read_dir(&dir).unwrap() {
+    let read_dir = fs::read_dir(dir).map_err(|err| format!("{}: {err}", dir.display()))?;
+    for path_ent in read_dir {
         if path_ent.is_err() {
             continue;
         }
 
         let de = path_ent.unwrap();
 
-        let metadata = de.metadata().unwrap();
+        let Ok(metadata) = de.metadata() else {
+            continue;
+        };
         if !metadata.is_file() {
             continue;
         }
@@ -32,134 +56,214 @@ where
     Ok(())
 }
 
-#[derive(Default, Copy, Clone)]
-struct MipInfo {
-    pub size: UVec2,
-    pub offset: u32,
+/// Tiled mip chain of one image, smallest level first, padded at the front
+/// so that level 0 starts on a page boundary.
+struct MipChain {
+    size: UVec2,
+    format: TextureFormat,
+    num_mips: u32,
+    mip0_page_offset: u32,
+    data: Vec<u8>,
 }
 
-fn pack_texture(png_path: &PathBuf, out_path: &PathBuf) -> Result<(), String> {
-    let decoder = png::Decoder::new(BufReader::new(fs::File::open(&png_path).unwrap()));
+fn pack_mip_chain(png_path: &Path, settings: &TextureSettings) -> Result<MipChain, String> {
+    let image = Image::read_png(png_path)?;
+    mip_chain_from_image(image, settings).map_err(|err| format!("{}: {err}", png_path.display()))
+}
 
-    let mut reader = decoder.read_info().unwrap();
-    let size: UVec2 = reader.info().size().into();
-    assert!(size.x > 0 && size.y > 0);
-    assert_eq!(reader.info().bit_depth, png::BitDepth::Eight);
-    assert_eq!(reader.info().color_type, png::ColorType::Rgba);
+fn mip_chain_from_image(mut image: Image, settings: &TextureSettings) -> Result<MipChain, String> {
+    if settings.normal_map {
+        image = prepare_normals(&image, settings.flip_green, settings.reconstruct_z)?;
+    }
+    let format = settings
+        .format
+        .unwrap_or_else(|| TextureFormat::for_pixel_format(image.format));
 
-    let pot_size = UVec2::new(size.x.next_power_of_two(), size.y.next_power_of_two());
-    let num_mips = u32::min(size.x.ilog2(), size.y.ilog2()) + 1;
-    assert!(num_mips <= 12);
+    let size = image.size;
+    let num_mips = if settings.mips {
+        u32::min(size.x.ilog2(), size.y.ilog2()) + 1
+    } else {
+        1
+    };
 
-    let mut mip_infos = SmallVec::<[MipInfo; 12]>::new();
-    mip_infos.resize(num_mips as _, MipInfo::default());
+    let layout = format
+        .mip_chain_layout(size, num_mips)
+        .map_err(|err| err.to_string())?;
+    let level_sizes: Vec<_> = layout.levels.iter().map(|level| level.size).collect();
+    let mut data = vec![0_u8; layout.total_size as usize];
+    let mut level_images = generate_mips(&image, &level_sizes, settings);
+    if settings.normal_map && format == TextureFormat::LumAlpha {
+        for level_image in &mut level_images {
+            *level_image = normals_to_lum_alpha(level_image);
+        }
+    }
+    for (level, level_image) in layout.levels.iter().zip(&level_images) {
+        tile_level(
+            level_image,
+            format,
+            settings.dither,
+            settings.etc1_quality,
+            &mut data[level.offset as usize..],
+        );
+    }
 
-    let mut total_size = 0_u32;
-    for level in (0..num_mips).rev() {
-        let mip_info = &mut mip_infos[level as usize];
-        let level_size = if level == 0 {
-            size
-        } else {
-            UVec2::max(UVec2::splat(1), pot_size >> level)
-        };
+    Ok(MipChain {
+        size,
+        format,
+        num_mips,
+        mip0_page_offset: layout.base_page(),
+        data,
+    })
+}
 
-        let alloc_size = Translator::alloc_size(level_size, 32);
-        mip_info.size = level_size;
-        mip_info.offset = total_size;
-        total_size += alloc_size;
-    }
+fn write_ctx(out_path: &Path, header: &CtxHeader, data: &[u8]) -> Result<(), String> {
+    let err = |err: &dyn std::fmt::Display| format!("{}: {err}", out_path.display());
+    let out_f = fs::File::create(out_path).map_err(|e| err(&e))?;
+    vc4_ctx::write(BufWriter::new(out_f), header, data).map_err(|e| err(&e))
+}
 
-    let mut tmp_buf = Vec::<u8>::new();
-    tmp_buf.resize(total_size as _, 0);
-    let mip_info_0 = &mip_infos[0];
-    let (mut prev_translator, mut prev_alloc_size) =
-        Translator::new_with_alloc_size(mip_info_0.size, 32);
-
-    for y in (0..mip_info_0.size.y).rev() {
-        let buf_slice = &mut tmp_buf[mip_info_0.offset as usize..total_size as usize];
-        let row = reader.next_row().unwrap().unwrap();
-        for x in 0..mip_info_0.size.x {
-            let xs = x as usize;
-            let offset = prev_translator
-                .coordinate_to_tile_address(UVec2::new(x, y))
-                .offset as usize;
-            buf_slice[offset] = row.data()[xs * 4 + 2];
-            buf_slice[offset + 1] = row.data()[xs * 4 + 1];
-            buf_slice[offset + 2] = row.data()[xs * 4 + 0];
-            buf_slice[offset + 3] = row.data()[xs * 4 + 3];
-        }
+fn ctx_header(chain: &MipChain, data_size: u32, settings: &TextureSettings) -> CtxHeader {
+    let default_min_filter = if chain.num_mips > 1 {
+        MinFilter::LinearMipLinear
+    } else {
+        MinFilter::Linear
+    };
+    CtxHeader {
+        data_size,
+        width: chain.size.x as u16,
+        height: chain.size.y as u16,
+        num_mips: chain.num_mips as u8,
+        data_type: chain.format.data_type(),
+        tiling: CtxTiling::Auto,
+        mag_filt: settings.mag_filter as u8,
+        min_filt: settings.min_filter.unwrap_or(default_min_filter) as u8,
+        wrap_s: settings.wrap_s.unwrap_or(Wrap::Repeat) as u8,
+        wrap_t: settings.wrap_t.unwrap_or(Wrap::Repeat) as u8,
+        mip0_page_offset: chain.mip0_page_offset,
+        etc_flip: chain.format.etc_flip(),
+        cube_map: false,
+        cube_map_stride: 0,
     }
+}
 
-    for level in 1..mip_infos.len() {
-        let mip_info = &mip_infos[level];
-        let prev_mip_info = &mip_infos[level - 1];
-        let (translator, alloc_size) = Translator::new_with_alloc_size(mip_info.size, 32);
-
-        for y in 0..mip_info.size.y {
-            for x in 0..mip_info.size.x {
-                let mut sum_pixel = [0_u16; 4];
-                {
-                    let buf_slice = &tmp_buf[prev_mip_info.offset as usize
-                        ..(prev_mip_info.offset + prev_alloc_size) as usize];
-                    const DELTAS: [UVec2; 4] = [
-                        UVec2::new(0, 0),
-                        UVec2::new(1, 0),
-                        UVec2::new(0, 1),
-                        UVec2::new(1, 1),
-                    ];
-                    for delta in DELTAS {
-                        let offset = prev_translator
-                            .coordinate_to_tile_address(UVec2::new(x, y) * 2 + delta)
-                            .offset as usize;
-                        for i in 0..4 {
-                            sum_pixel[i] += buf_slice[offset + i] as u16;
-                        }
-                    }
-                }
+/// Packs one PNG into a `.ctx` texture.
+pub fn pack_texture(
+    png_path: &Path,
+    out_path: &Path,
+    settings: &TextureSettings,
+) -> Result<(), String> {
+    let chain = pack_mip_chain(png_path, settings)?;
+    let header = ctx_header(&chain, chain.data.len() as u32, settings);
+    write_ctx(out_path, &header, &chain.data)
+}
 
-                let buf_slice =
-                    &mut tmp_buf[mip_info.offset as usize..(mip_info.offset + alloc_size) as usize];
-                let offset = translator
-                    .coordinate_to_tile_address(UVec2::new(x, y))
-                    .offset as usize;
-                for i in 0..4 {
-                    buf_slice[offset + i] = (sum_pixel[i] >> 2) as _;
-                }
-            }
+/// Packs six faces into one BO: each face is a full mip chain, and faces
+/// follow each other at the page-aligned cube map stride.
+fn pack_cube_map(
+    face_paths: &[PathBuf; 6],
+    out_path: &Path,
+    settings: &TextureSettings,
+) -> Result<(), String> {
+    let mut chains = Vec::with_capacity(6);
+    for face_path in face_paths {
+        chains.push(pack_mip_chain(face_path, settings)?);
+    }
+
+    let chain_0 = &chains[0];
+    if chain_0.size.x != chain_0.size.y {
+        return Err(format!(
+            "cube map face {} is not square",
+            face_paths[0].display()
+        ));
+    }
+    for (face_path, chain) in face_paths.iter().zip(&chains) {
+        if chain.size != chain_0.size || chain.format != chain_0.format {
+            return Err(format!(
+                "cube map face {} doesn't match the size or format of {}",
+                face_path.display(),
+                face_paths[0].display()
+            ));
         }
+    }
 
-        prev_translator = translator;
-        prev_alloc_size = alloc_size;
+    let stride = (chain_0.data.len() as u32).next_multiple_of(4096);
+    let mut data = vec![0_u8; (stride * 6) as usize];
+    for (face, chain) in chains.iter().enumerate() {
+        let face_offset = face * stride as usize;
+        data[face_offset..face_offset + chain.data.len()].copy_from_slice(&chain.data);
     }
 
-    let mip0_page_offset = (mip_info_0.offset + 4095) / 4096;
-    let mip_padding = mip0_page_offset * 4096 - mip_info_0.offset;
-
-    let mut header_data = [0_u8; 16];
-    header_data[0..4].copy_from_slice(0x005072C2_u32.to_le_bytes().as_slice());
-    header_data[4..8].copy_from_slice(total_size.to_le_bytes().as_slice());
-    header_data[8..10].copy_from_slice((num_mips as u16).to_le_bytes().as_slice());
-    header_data[10..12].copy_from_slice((mip0_page_offset as u16).to_le_bytes().as_slice());
-    header_data[12..14].copy_from_slice(((mip_info_0.size.x) as u16).to_le_bytes().as_slice());
-    header_data[14..16].copy_from_slice(((mip_info_0.size.y) as u16).to_le_bytes().as_slice());
-
-    let mut out_f = fs::File::create(out_path).unwrap();
-    out_f.write(header_data.as_slice()).unwrap();
-    let mut c = ZlibEncoder::new(out_f, flate2::Compression::best());
-    for _ in 0..mip_padding {
-        c.write_all(&[0]).unwrap();
+    let header = CtxHeader {
+        wrap_s: settings.wrap_s.unwrap_or(Wrap::Clamp) as u8,
+        wrap_t: settings.wrap_t.unwrap_or(Wrap::Clamp) as u8,
+        cube_map: true,
+        cube_map_stride: stride,
+        ..ctx_header(chain_0, data.len() as u32, settings)
+    };
+    write_ctx(out_path, &header, &data)
+}
+
+/// Packs the sprites of `atlas_dir` into one texture, in the layout
+/// [`write_atlas_module`] describes.
+fn pack_atlas(atlas_dir: &Path, out_path: &Path, settings: &AtlasSettings) -> Result<(), String> {
+    let layout = atlas::read_atlas_layout(atlas_dir, settings)?;
+    let mut sprites = Vec::new();
+    for (name, png_path) in atlas::sprite_paths(atlas_dir)? {
+        sprites.push((name, Image::read_png(&png_path)?));
     }
-    c.write_all(tmp_buf.as_slice()).unwrap();
-    c.finish().unwrap();
+    let image = build_atlas_image(&sprites, &layout, settings.padding);
 
+    let texture = &settings.texture;
+    let chain = mip_chain_from_image(image, texture)
+        .map_err(|err| format!("{}: {err}", atlas_dir.display()))?;
+    let header = CtxHeader {
+        wrap_s: texture.wrap_s.unwrap_or(Wrap::Clamp) as u8,
+        wrap_t: texture.wrap_t.unwrap_or(Wrap::Clamp) as u8,
+        ..ctx_header(&chain, chain.data.len() as u32, texture)
+    };
+    write_ctx(out_path, &header, &chain.data)
+}
+
+/// Calls `f` with every `name.atlas` directory of `resources_dir`.
+fn for_each_atlas_dir<F>(resources_dir: &Path, mut f: F) -> Result<(), String>
+where
+    F: FnMut(PathBuf) -> Result<(), String>,
+{
+    let read_dir =
+        fs::read_dir(resources_dir).map_err(|err| format!("{}: {err}", resources_dir.display()))?;
+    for de in read_dir.flatten() {
+        let path = de.path();
+        if path.is_dir() && path.extension().unwrap_or("".as_ref()) == "atlas" {
+            f(path)?;
+        }
+    }
     Ok(())
 }
 
-fn prune_generated_dir(generated_dir: &PathBuf, resources_dir: &PathBuf) -> Result<bool, String> {
+/// Returns the cube map name and face index if `png_path` is named like
+/// `name_px.png`.
+fn cube_face_of(png_path: &Path) -> Option<(String, usize)> {
+    let stem = png_path.file_stem()?.to_str()?;
+    CUBE_FACE_SUFFIXES
+        .iter()
+        .enumerate()
+        .find_map(|(face, suffix)| Some((stem.strip_suffix(suffix)?.to_string(), face)))
+}
+
+fn cube_face_paths(resources_dir: &Path, name: &str) -> [PathBuf; 6] {
+    CUBE_FACE_SUFFIXES.map(|suffix| resources_dir.join(format!("{name}{suffix}.png")))
+}
+
+fn prune_generated_dir(generated_dir: &Path, resources_dir: &Path) -> Result<bool, String> {
     let mut pruned_dir = false;
-    for_each_file_ext_in_dir(&generated_dir, "ctx", |ctx_path, _| {
-        let stem = ctx_path.file_stem().unwrap();
-        if !resources_dir.join(stem).with_extension("png").exists() {
+    for_each_file_ext_in_dir(generated_dir, "ctx", |ctx_path, _| {
+        let stem = ctx_path.file_stem().unwrap().to_str().unwrap();
+        let png_exists = resources_dir.join(stem).with_extension("png").exists();
+        let cube_exists = cube_face_paths(resources_dir, stem)
+            .iter()
+            .all(|path| path.exists());
+        let atlas_exists = resources_dir.join(stem).with_extension("atlas").is_dir();
+        if !png_exists && !cube_exists && !atlas_exists {
             fs::remove_file(ctx_path).ok();
             pruned_dir = true;
         }
@@ -168,21 +272,245 @@ fn prune_generated_dir(generated_dir: &PathBuf, resources_dir: &PathBuf) -> Resu
     Ok(pruned_dir)
 }
 
-pub fn pack_textures(resources_dir: &PathBuf) -> Result<(), String> {
+/// What an output of the generated directory is packed from.
+enum PackJob {
+    Texture {
+        png_path: PathBuf,
+        settings: TextureSettings,
+    },
+    CubeMap {
+        face_paths: [PathBuf; 6],
+        settings: TextureSettings,
+    },
+    Atlas {
+        atlas_dir: PathBuf,
+        settings: AtlasSettings,
+    },
+}
+
+struct Output {
+    out_path: PathBuf,
+    /// Every file the output is built from, manifests included.
+    in_paths: Vec<PathBuf>,
+    job: PackJob,
+}
+
+impl Output {
+    /// Packs the output unless its content hash is the one recorded in
+    /// `index` and it was written in the current `.ctx` version. Returns the
+    /// content hash.
+    fn pack_if_changed(&self, index: &CacheIndex) -> Result<u64, String> {
+        let settings: &dyn std::fmt::Debug = match &self.job {
+            PackJob::Texture { settings, .. } | PackJob::CubeMap { settings, .. } => settings,
+            PackJob::Atlas { settings, .. } => settings,
+        };
+        let hash = content_hash(&self.in_paths, settings)?;
+        let name = self.out_path.file_name().unwrap().to_string_lossy();
+        let current_version = fs::File::open(&self.out_path)
+            .is_ok_and(|out_f| vc4_ctx::peek_version(out_f) == Some(vc4_ctx::VERSION));
+        if index.hashes.get(name.as_ref()) == Some(&hash) && current_version {
+            return Ok(hash);
+        }
+
+        match &self.job {
+            PackJob::Texture { png_path, settings } => {
+                pack_texture(png_path, &self.out_path, settings)?
+            }
+            PackJob::CubeMap {
+                face_paths,
+                settings,
+            } => pack_cube_map(face_paths, &self.out_path, settings)?,
+            PackJob::Atlas {
+                atlas_dir,
+                settings,
+            } => pack_atlas(atlas_dir, &self.out_path, settings)?,
+        }
+        Ok(hash)
+    }
+}
+
+/// Packs the outputs that changed on every core, then records their
+/// content hashes. Outputs that failed are left out of the index, so that
+/// they are packed again next time.
+fn pack_outputs(outputs: &[Output], generated_dir: &Path) -> Result<(), String> {
+    let old_index = CacheIndex::read(generated_dir);
+    let next_output = AtomicUsize::new(0);
+    let num_threads = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(outputs.len());
+    let mut results: Vec<(usize, Result<u64, String>)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..num_threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let i = next_output.fetch_add(1, Ordering::Relaxed);
+                        let Some(output) = outputs.get(i) else {
+                            break;
+                        };
+                        results.push((i, output.pack_if_changed(&old_index)));
+                    }
+                    results
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    results.sort_by_key(|&(i, _)| i);
+
+    let mut index = CacheIndex::default();
+    let mut first_err = None;
+    for (i, result) in results {
+        match result {
+            Ok(hash) => {
+                let name = outputs[i].out_path.file_name().unwrap();
+                index
+                    .hashes
+                    .insert(name.to_string_lossy().into_owned(), hash);
+            }
+            Err(err) => {
+                first_err.get_or_insert(err);
+            }
+        }
+    }
+    index.write(generated_dir)?;
+    first_err.map_or(Ok(()), Err)
+}
+
+/// Packs every `name.png` in `resources_dir` into `generated/name.ctx`,
+/// with the import settings of its `name.png.toml` manifest if there is
+/// one.
+///
+/// Six PNGs named `name_px.png`, `name_nx.png`, `name_py.png`,
+/// `name_ny.png`, `name_pz.png` and `name_nz.png` are packed together into
+/// the cube map `generated/name.ctx` instead, with the settings of
+/// `name_px.png.toml`.
+///
+/// The sprite PNGs of each `name.atlas` directory are packed together into
+/// `generated/name.ctx`, with the settings of its `atlas.toml`.
+///
+/// Only outputs whose inputs, settings or packer version changed since they
+/// were packed are rebuilt, as recorded in `generated/cache.txt`.
+///
+/// Returns the PNGs, manifests and atlas directories read, for
+/// `cargo:rerun-if-changed`.
+pub fn pack_textures(resources_dir: &Path) -> Result<Vec<PathBuf>, String> {
     let generated_dir = resources_dir.join("generated");
-    fs::create_dir_all(&generated_dir).unwrap();
-
-    prune_generated_dir(&generated_dir, &resources_dir)?;
-    for_each_file_ext_in_dir(resources_dir, "png", |png_path, png_metadata| {
-        let out_path = generated_dir
-            .join(png_path.file_name().unwrap())
-            .with_extension("ctx");
-        let out_metadata = out_path.metadata();
-        if out_metadata.is_err()
-            || out_metadata.unwrap().modified().unwrap() < png_metadata.modified().unwrap()
-        {
-            pack_texture(&png_path, &out_path)?;
+    fs::create_dir_all(&generated_dir)
+        .map_err(|err| format!("{}: {err}", generated_dir.display()))?;
+
+    prune_generated_dir(&generated_dir, resources_dir)?;
+    let mut outputs = Vec::new();
+    for_each_file_ext_in_dir(resources_dir, "png", |png_path, _| {
+        let manifest_path =
+            Some(TextureSettings::manifest_path(&png_path)).filter(|path| path.exists());
+        let read_settings = || match &manifest_path {
+            Some(path) => TextureSettings::read_manifest(path),
+            None => Ok(TextureSettings::default()),
+        };
+
+        if let Some((name, face)) = cube_face_of(&png_path) {
+            let face_paths = cube_face_paths(resources_dir, &name);
+            if face_paths.iter().all(|path| path.exists()) {
+                // Added once, when visiting the first face.
+                if face == 0 {
+                    outputs.push(Output {
+                        out_path: generated_dir.join(&name).with_extension("ctx"),
+                        in_paths: face_paths
+                            .iter()
+                            .cloned()
+                            .chain(manifest_path.clone())
+                            .collect(),
+                        job: PackJob::CubeMap {
+                            face_paths,
+                            settings: read_settings()?,
+                        },
+                    });
+                }
+                return Ok(());
+            }
         }
+
+        outputs.push(Output {
+            out_path: generated_dir
+                .join(png_path.file_name().unwrap())
+                .with_extension("ctx"),
+            in_paths: [png_path.clone()]
+                .into_iter()
+                .chain(manifest_path.clone())
+                .collect(),
+            job: PackJob::Texture {
+                png_path,
+                settings: read_settings()?,
+            },
+        });
         Ok(())
-    })
+    })?;
+
+    for_each_atlas_dir(resources_dir, |atlas_dir| {
+        let manifest_path =
+            Some(AtlasSettings::manifest_path(&atlas_dir)).filter(|path| path.exists());
+        let sprite_paths = atlas::sprite_paths(&atlas_dir)?;
+        // The directory itself catches added and removed sprites.
+        let in_paths: Vec<_> = [atlas_dir.clone()]
+            .into_iter()
+            .chain(sprite_paths.into_iter().map(|(_, path)| path))
+            .chain(manifest_path)
+            .collect();
+        outputs.push(Output {
+            out_path: generated_dir
+                .join(atlas_dir.file_stem().unwrap())
+                .with_extension("ctx"),
+            in_paths,
+            job: PackJob::Atlas {
+                settings: AtlasSettings::read(&atlas_dir)?,
+                atlas_dir,
+            },
+        });
+        Ok(())
+    })?;
+
+    pack_outputs(&outputs, &generated_dir)?;
+    let mut inputs: Vec<_> = outputs
+        .into_iter()
+        .flat_map(|output| output.in_paths)
+        .collect();
+    inputs.sort();
+    inputs.dedup();
+    Ok(inputs)
+}
+
+/// Writes a Rust module with an `AtlasRect` constant for every sprite of
+/// every `name.atlas` directory of `resources_dir`, in a `name` submodule
+/// along with the path of the packed texture. Names are lowercased and
+/// other characters than `[a-z0-9]` replaced by `_`, failing if that makes
+/// two atlases or two sprites of an atlas collide.
+///
+/// The module is only rewritten when its contents change, so that it
+/// doesn't trigger rebuilds.
+pub fn write_atlas_module(resources_dir: &Path, module_path: &Path) -> Result<(), String> {
+    let generated_dir = resources_dir.join("generated");
+    let mut atlases = Vec::new();
+    for_each_atlas_dir(resources_dir, |atlas_dir| {
+        let name = atlas_dir
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        let settings = AtlasSettings::read(&atlas_dir)?;
+        let layout = atlas::read_atlas_layout(&atlas_dir, &settings)?;
+        let ctx_path = generated_dir.join(&name).with_extension("ctx");
+        atlases.push((atlas_dir, ctx_path, layout));
+        Ok(())
+    })?;
+    atlases.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let source = atlas::atlas_module_source(&atlases)?;
+    if fs::read_to_string(module_path).is_ok_and(|old| old == source) {
+        return Ok(());
+    }
+    fs::write(module_path, source).map_err(|err| format!("{}: {err}", module_path.display()))
 }
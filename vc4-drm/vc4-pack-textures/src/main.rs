@@ -0,0 +1,274 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use vc4_ctx::{CtxHeader, CtxTiling};
+use vc4_image_addr::glam::UVec2;
+use vc4_image_addr::MipLevelLayout;
+use vc4_pack_textures::*;
+
+const USAGE: &str = "\
+usage: vc4-pack-textures pack <png or dir> [-o <ctx or dir>] [options]
+       vc4-pack-textures inspect <ctx>
+       vc4-pack-textures unpack <ctx> [-o <png>]
+
+pack options override the settings of the name.png.toml manifests:
+  --format <format>       rgba8888, rgbx8888, rgba4444, rgba5551, rgb565,
+                          luminance, lum_alpha or etc1
+  --dither <dither>       none, ordered or error_diffusion
+  --etc1-quality <q>      fast, medium or high
+  --mip-filter <filter>   box, kaiser or lanczos
+  --no-mips               only store the base level
+  --normal-map            pack as a normal map
+
+unpack writes name.png for the base level and name.mipN.png for the others,
+with the cube map face suffix (_px, _nx, ...) of each face of a cube map.";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("pack") => pack(&args[1..]),
+        Some("inspect") => inspect(&args[1..]),
+        Some("unpack") => unpack(&args[1..]),
+        Some("-h" | "--help") => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Arguments of a command: one input path, `-o` and the options taking a
+/// value, which are returned as manifest keys.
+struct Args {
+    input: PathBuf,
+    output: Option<PathBuf>,
+    overrides: toml::Table,
+}
+
+fn parse_args(args: &[String], allow_overrides: bool) -> Result<Args, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut overrides = toml::Table::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{arg} expects a value"))
+        };
+        let key = match arg.as_str() {
+            "-o" => {
+                output = Some(PathBuf::from(value()?));
+                continue;
+            }
+            "--no-mips" if allow_overrides => {
+                overrides.insert("mips".into(), false.into());
+                continue;
+            }
+            "--normal-map" if allow_overrides => {
+                overrides.insert("normal_map".into(), true.into());
+                continue;
+            }
+            "--format" | "--dither" | "--etc1-quality" | "--mip-filter" if allow_overrides => {
+                arg[2..].replace('-', "_")
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}\n\n{USAGE}")),
+            _ if input.is_none() => {
+                input = Some(PathBuf::from(arg));
+                continue;
+            }
+            _ => return Err(format!("unexpected argument {arg}\n\n{USAGE}")),
+        };
+        overrides.insert(key, value()?.into());
+    }
+    Ok(Args {
+        input: input.ok_or_else(|| USAGE.to_string())?,
+        output,
+        overrides,
+    })
+}
+
+/// Settings of the manifest of `png_path`, if any, with `overrides`
+/// replacing its keys.
+fn read_settings(png_path: &Path, overrides: &toml::Table) -> Result<TextureSettings, String> {
+    let manifest_path = TextureSettings::manifest_path(png_path);
+    let mut table = if manifest_path.exists() {
+        let text = fs::read_to_string(&manifest_path)
+            .map_err(|err| format!("{}: {err}", manifest_path.display()))?;
+        text.parse::<toml::Table>()
+            .map_err(|err| format!("{}: {err}", manifest_path.display()))?
+    } else {
+        toml::Table::new()
+    };
+    table.extend(overrides.clone());
+    toml::Value::Table(table)
+        .try_into()
+        .map_err(|err| format!("{}: {err}", png_path.display()))
+}
+
+fn pack(args: &[String]) -> Result<(), String> {
+    let args = parse_args(args, true)?;
+    let mut png_paths = Vec::new();
+    if args.input.is_dir() {
+        for de in fs::read_dir(&args.input)
+            .map_err(|err| format!("{}: {err}", args.input.display()))?
+            .flatten()
+        {
+            let path = de.path();
+            if path.is_file() && path.extension().unwrap_or("".as_ref()) == "png" {
+                png_paths.push(path);
+            }
+        }
+        png_paths.sort();
+    } else {
+        png_paths.push(args.input.clone());
+    }
+
+    for png_path in &png_paths {
+        let out_path = match &args.output {
+            Some(output) if args.input.is_dir() => output
+                .join(png_path.file_name().unwrap())
+                .with_extension("ctx"),
+            Some(output) => output.clone(),
+            None => png_path.with_extension("ctx"),
+        };
+        if let Some(dir) = out_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|err| format!("{}: {err}", dir.display()))?;
+        }
+        pack_texture(
+            png_path,
+            &out_path,
+            &read_settings(png_path, &args.overrides)?,
+        )?;
+        println!("{} -> {}", png_path.display(), out_path.display());
+    }
+    Ok(())
+}
+
+fn read_ctx(ctx_path: &Path) -> Result<(CtxHeader, Vec<u8>), String> {
+    let err = |err: &dyn std::fmt::Display| format!("{}: {err}", ctx_path.display());
+    let file = fs::File::open(ctx_path).map_err(|e| err(&e))?;
+    vc4_ctx::read(std::io::BufReader::new(file)).map_err(|e| err(&e))
+}
+
+/// Format and mip layout of a `.ctx` file, checked against its header.
+fn ctx_layout(
+    ctx_path: &Path,
+    header: &CtxHeader,
+) -> Result<(TextureFormat, Vec<MipLevelLayout>), String> {
+    let err = |err: &str| format!("{}: {err}", ctx_path.display());
+    let format = TextureFormat::from_data_type(header.data_type)
+        .ok_or_else(|| err(&format!("unsupported data type {}", header.data_type)))?;
+    let size = UVec2::new(header.width as u32, header.height as u32);
+    let layout = format
+        .mip_chain_layout(size, header.num_mips as u32)
+        .map_err(|e| err(&e.to_string()))?;
+
+    let faces = if header.cube_map { 6 } else { 1 };
+    let face_stride = if header.cube_map {
+        header.cube_map_stride
+    } else {
+        layout.total_size
+    };
+    if layout.base_page() != header.mip0_page_offset
+        || layout.total_size > face_stride
+        || face_stride.checked_mul(faces) != Some(header.data_size)
+    {
+        return Err(err("header doesn't match the packed mip chain layout"));
+    }
+    Ok((format, layout.levels))
+}
+
+/// Whether the TMU reads a level in LT-format, following the header's
+/// tiling override.
+fn is_lt_format(header: &CtxHeader, level: &MipLevelLayout) -> bool {
+    match header.tiling {
+        CtxTiling::T => false,
+        CtxTiling::LT => true,
+        CtxTiling::Auto => level.is_lt_format,
+    }
+}
+
+fn inspect(args: &[String]) -> Result<(), String> {
+    let args = parse_args(args, false)?;
+    let (header, _) = read_ctx(&args.input)?;
+    let (format, levels) = ctx_layout(&args.input, &header)?;
+
+    println!("{}", args.input.display());
+    println!("  size:       {}x{}", header.width, header.height);
+    println!("  format:     {format:?} (data type {})", header.data_type);
+    println!("  data size:  {} bytes", header.data_size);
+    println!("  mips:       {}", header.num_mips);
+    println!("  tiling:     {:?}", header.tiling);
+    println!("  mag filter: {}", header.mag_filt);
+    println!("  min filter: {}", header.min_filt);
+    println!("  wrap s/t:   {}/{}", header.wrap_s, header.wrap_t);
+    println!("  base page:  {}", header.mip0_page_offset);
+    println!("  etc flip:   {}", header.etc_flip);
+    if header.cube_map {
+        println!("  cube map:   stride {} bytes", header.cube_map_stride);
+    }
+    println!();
+    println!("  level       size     offset      bytes  tiling");
+    for (level, mip) in levels.iter().enumerate() {
+        let tiling = if is_lt_format(&header, mip) {
+            "LT"
+        } else {
+            "T"
+        };
+        println!(
+            "  {level:>5}  {:>9}  {:>9}  {:>9}  {tiling}",
+            format!("{}x{}", mip.size.x, mip.size.y),
+            mip.offset,
+            mip.alloc_size,
+        );
+    }
+    Ok(())
+}
+
+fn unpack(args: &[String]) -> Result<(), String> {
+    let args = parse_args(args, false)?;
+    let (header, data) = read_ctx(&args.input)?;
+    let (format, levels) = ctx_layout(&args.input, &header)?;
+    if header.tiling != CtxTiling::Auto {
+        return Err(format!(
+            "{}: only textures with automatic tiling can be unpacked",
+            args.input.display()
+        ));
+    }
+
+    let out_path = args
+        .output
+        .unwrap_or_else(|| args.input.with_extension("png"));
+    let stem = out_path.with_extension("");
+    let faces: &[&str] = if header.cube_map {
+        &CUBE_FACE_SUFFIXES
+    } else {
+        &[""]
+    };
+    for (face, suffix) in faces.iter().enumerate() {
+        let face_offset = face * header.cube_map_stride as usize;
+        for (level, mip) in levels.iter().enumerate() {
+            let mut png_path = stem.clone().into_os_string();
+            png_path.push(suffix);
+            if level > 0 {
+                png_path.push(format!(".mip{level}"));
+            }
+            png_path.push(".png");
+            let png_path = PathBuf::from(png_path);
+
+            let offset = face_offset + mip.offset as usize;
+            let tiled = &data[offset..offset + mip.alloc_size as usize];
+            untile_level(tiled, mip.size, format).write_png(&png_path)?;
+            println!("{}", png_path.display());
+        }
+    }
+    Ok(())
+}
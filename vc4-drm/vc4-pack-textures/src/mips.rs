@@ -0,0 +1,300 @@
+use crate::image::{Image, PixelFormat};
+use crate::settings::{MipFilter, TextureSettings};
+use std::f32::consts::PI;
+use vc4_image_addr::glam::UVec2;
+
+impl MipFilter {
+    /// Half width of the kernel, in destination pixels.
+    fn support(self) -> f32 {
+        match self {
+            MipFilter::Box => 0.5,
+            MipFilter::Kaiser | MipFilter::Lanczos => 3.0,
+        }
+    }
+
+    fn weight(self, x: f32) -> f32 {
+        let x = x.abs();
+        if x >= self.support() {
+            return 0.0;
+        }
+        match self {
+            MipFilter::Box => 1.0,
+            MipFilter::Kaiser => {
+                const ALPHA: f32 = 4.0;
+                let t = x / self.support();
+                sinc(x) * bessel_i0(ALPHA * (1.0 - t * t).sqrt()) / bessel_i0(ALPHA)
+            }
+            MipFilter::Lanczos => sinc(x) * sinc(x / self.support()),
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Zeroth order modified Bessel function of the first kind.
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-7 {
+        term *= (x / (2.0 * k)) * (x / (2.0 * k));
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+/// Source pixels and weights contributing to each destination pixel of a
+/// row or column. Taps past the edges are clamped, and weights normalized
+/// so that a constant image stays constant.
+fn resample_taps(src_len: u32, dst_len: u32, filter: MipFilter) -> Vec<Vec<(u32, f32)>> {
+    let scale = src_len as f32 / dst_len as f32;
+    (0..dst_len)
+        .map(|d| {
+            let mut taps: Vec<(u32, f32)> = if filter == MipFilter::Box {
+                // Exact coverage of each source pixel by the destination
+                // pixel's footprint, which handles NPOT ratios.
+                let (start, end) = (d as f32 * scale, (d + 1) as f32 * scale);
+                (start.floor() as u32..u32::min(end.ceil() as u32, src_len))
+                    .map(|s| {
+                        let overlap = f32::min(end, s as f32 + 1.0) - f32::max(start, s as f32);
+                        (s, overlap)
+                    })
+                    .collect()
+            } else {
+                let center = (d as f32 + 0.5) * scale;
+                let radius = filter.support() * scale;
+                let first = (center - radius).floor() as i64;
+                let last = (center + radius).ceil() as i64;
+                (first..=last)
+                    .map(|s| {
+                        let x = (s as f32 + 0.5 - center) / scale;
+                        (s.clamp(0, src_len as i64 - 1) as u32, filter.weight(x))
+                    })
+                    .collect()
+            };
+            let total: f32 = taps.iter().map(|&(_, w)| w).sum();
+            taps.retain(|&(_, w)| w != 0.0);
+            for (_, w) in &mut taps {
+                *w /= total;
+            }
+            taps
+        })
+        .collect()
+}
+
+/// Floating point copy of an image, with color channels in linear light
+/// when it holds sRGB colors.
+#[derive(Clone)]
+struct LinearImage {
+    size: UVec2,
+    format: PixelFormat,
+    data: Vec<f32>,
+}
+
+impl LinearImage {
+    fn from_image(image: &Image, srgb: bool) -> Self {
+        let to_linear = srgb_to_linear_table();
+        let alpha_channel = image.format.alpha_channel();
+        let channels = image.format.channels();
+        let data = image
+            .data
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                if srgb && Some(i % channels) != alpha_channel {
+                    to_linear[v as usize]
+                } else {
+                    v as f32 / 255.0
+                }
+            })
+            .collect();
+        Self {
+            size: image.size,
+            format: image.format,
+            data,
+        }
+    }
+
+    fn to_image(&self, srgb: bool) -> Image {
+        let alpha_channel = self.format.alpha_channel();
+        let channels = self.format.channels();
+        let data = self
+            .data
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                if srgb && Some(i % channels) != alpha_channel {
+                    linear_to_srgb(v)
+                } else {
+                    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+                }
+            })
+            .collect();
+        Image {
+            size: self.size,
+            format: self.format,
+            data,
+        }
+    }
+
+    /// Separable resampling to `new_size`, horizontally then vertically.
+    fn resample(&self, new_size: UVec2, filter: MipFilter) -> Self {
+        let channels = self.format.channels();
+        let x_taps = resample_taps(self.size.x, new_size.x, filter);
+        let y_taps = resample_taps(self.size.y, new_size.y, filter);
+
+        let mut rows = vec![0_f32; (new_size.x * self.size.y) as usize * channels];
+        for y in 0..self.size.y as usize {
+            for (x, taps) in x_taps.iter().enumerate() {
+                let dst = (y * new_size.x as usize + x) * channels;
+                for &(s, w) in taps {
+                    let src = (y * self.size.x as usize + s as usize) * channels;
+                    for c in 0..channels {
+                        rows[dst + c] += self.data[src + c] * w;
+                    }
+                }
+            }
+        }
+
+        let mut data = vec![0_f32; (new_size.x * new_size.y) as usize * channels];
+        for (y, taps) in y_taps.iter().enumerate() {
+            for x in 0..new_size.x as usize {
+                let dst = (y * new_size.x as usize + x) * channels;
+                for &(s, w) in taps {
+                    let src = (s as usize * new_size.x as usize + x) * channels;
+                    for c in 0..channels {
+                        data[dst + c] += rows[src + c] * w;
+                    }
+                }
+            }
+        }
+
+        Self {
+            size: new_size,
+            format: self.format,
+            data,
+        }
+    }
+
+    /// Rescales RGB encoded normals to unit length.
+    fn renormalize(&mut self) {
+        let channels = self.format.channels();
+        if channels < 3 {
+            return;
+        }
+        for pixel in self.data.chunks_exact_mut(channels) {
+            let n: [f32; 3] = std::array::from_fn(|c| pixel[c] * 2.0 - 1.0);
+            let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+            if length > 1e-6 {
+                for c in 0..3 {
+                    pixel[c] = (n[c] / length) * 0.5 + 0.5;
+                }
+            }
+        }
+    }
+
+    /// Fraction of pixels passing an alpha test against `threshold`, with
+    /// alpha scaled by `scale`.
+    fn alpha_coverage(&self, threshold: f32, scale: f32) -> f32 {
+        let Some(alpha_channel) = self.format.alpha_channel() else {
+            return 1.0;
+        };
+        let channels = self.format.channels();
+        let passing = self
+            .data
+            .chunks_exact(channels)
+            .filter(|pixel| (pixel[alpha_channel] * scale).min(1.0) >= threshold)
+            .count();
+        passing as f32 / (self.data.len() / channels) as f32
+    }
+
+    /// Scales alpha so that as many pixels pass the alpha test as in the
+    /// base level, instead of thinning out with each level.
+    fn preserve_alpha_coverage(&mut self, threshold: f32, coverage: f32) {
+        let Some(alpha_channel) = self.format.alpha_channel() else {
+            return;
+        };
+        // Coverage grows with the scale, so bisect for the target.
+        let (mut low, mut high) = (0.0_f32, 8.0_f32);
+        for _ in 0..16 {
+            let mid = (low + high) / 2.0;
+            if self.alpha_coverage(threshold, mid) < coverage {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        let channels = self.format.channels();
+        for pixel in self.data.chunks_exact_mut(channels) {
+            pixel[alpha_channel] = (pixel[alpha_channel] * high).min(1.0);
+        }
+    }
+}
+
+/// Generates the image of every level in `level_sizes`, the first one being
+/// the size of `base`. Color textures are filtered in linear light when
+/// `settings.srgb` is set, normal maps are renormalized, and textures with
+/// an `alpha_test_threshold` keep the alpha test coverage of the base level.
+pub fn generate_mips(
+    base: &Image,
+    level_sizes: &[UVec2],
+    settings: &TextureSettings,
+) -> Vec<Image> {
+    assert_eq!(level_sizes[0], base.size);
+
+    let mut base = base.clone();
+    if settings.premultiply_alpha && !settings.normal_map {
+        base.premultiply_alpha();
+    }
+    let srgb = settings.srgb && !settings.normal_map;
+    let alpha_test_threshold = settings
+        .alpha_test_threshold
+        .filter(|_| !settings.normal_map);
+
+    let mut level = LinearImage::from_image(&base, srgb);
+    let coverage = alpha_test_threshold.map(|threshold| level.alpha_coverage(threshold, 1.0));
+    let mut levels = Vec::with_capacity(level_sizes.len());
+    levels.push(base);
+    for &size in &level_sizes[1..] {
+        // Each level is filtered from the previous one before its
+        // adjustments, so that they don't accumulate.
+        level = level.resample(size, settings.mip_filter);
+        let mut adjusted = level.clone();
+        if settings.normal_map {
+            adjusted.renormalize();
+        }
+        if let (Some(threshold), Some(coverage)) = (alpha_test_threshold, coverage) {
+            adjusted.preserve_alpha_coverage(threshold, coverage);
+        }
+        levels.push(adjusted.to_image(srgb));
+    }
+    levels
+}
+
+fn srgb_to_linear_table() -> [f32; 256] {
+    std::array::from_fn(|i| {
+        let v = i as f32 / 255.0;
+        if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        }
+    })
+}
+
+fn linear_to_srgb(v: f32) -> u8 {
+    let v = v.clamp(0.0, 1.0);
+    let v = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (v * 255.0).round() as u8
+}
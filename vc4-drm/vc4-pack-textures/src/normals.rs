@@ -0,0 +1,75 @@
+use crate::image::{Image, PixelFormat};
+
+fn decode(v: u8) -> f32 {
+    v as f32 / 255.0 * 2.0 - 1.0
+}
+
+fn encode(v: f32) -> u8 {
+    ((v.clamp(-1.0, 1.0) * 0.5 + 0.5) * 255.0).round() as u8
+}
+
+/// Converts a normal map PNG to unit length RGB normals, keeping any alpha
+/// channel of RGBA PNGs.
+///
+/// Two channel PNGs hold X and Y, and always have Z reconstructed. With
+/// `reconstruct_z`, RGB PNGs have their blue channel replaced as well.
+/// `flip_green` negates Y, converting DirectX style normal maps to the
+/// OpenGL and glTF convention.
+pub fn prepare_normals(
+    image: &Image,
+    flip_green: bool,
+    reconstruct_z: bool,
+) -> Result<Image, String> {
+    let (format, reconstruct_z) = match image.format {
+        PixelFormat::Luminance => {
+            return Err("normal maps need at least two channels".to_string());
+        }
+        PixelFormat::LumAlpha => (PixelFormat::Rgb, true),
+        format => (format, reconstruct_z),
+    };
+
+    let mut normals = Image::new(image.size, format);
+    for y in 0..image.size.y {
+        for x in 0..image.size.x {
+            let src = image.pixel(x, y);
+            let nx = decode(src[0]);
+            let mut ny = decode(src[1]);
+            if flip_green {
+                ny = -ny;
+            }
+            let nz = if reconstruct_z {
+                (1.0 - nx * nx - ny * ny).max(0.0).sqrt()
+            } else {
+                decode(src[2])
+            };
+
+            let length = (nx * nx + ny * ny + nz * nz).sqrt();
+            let n = if length > 1e-6 {
+                [nx / length, ny / length, nz / length]
+            } else {
+                [0.0, 0.0, 1.0]
+            };
+            let dst = normals.pixel_mut(x, y);
+            for c in 0..3 {
+                dst[c] = encode(n[c]);
+            }
+            if format == PixelFormat::Rgba {
+                dst[3] = src[3];
+            }
+        }
+    }
+    Ok(normals)
+}
+
+/// Keeps only X and Y of RGB normals, as luminance and alpha. Shaders
+/// reconstruct Z as `sqrt(1 - x * x - y * y)`.
+pub fn normals_to_lum_alpha(image: &Image) -> Image {
+    let mut two_channel = Image::new(image.size, PixelFormat::LumAlpha);
+    for y in 0..image.size.y {
+        for x in 0..image.size.x {
+            let src = image.pixel(x, y);
+            two_channel.pixel_mut(x, y).copy_from_slice(&src[0..2]);
+        }
+    }
+    two_channel
+}
@@ -0,0 +1,137 @@
+use crate::encode::{Dither, TextureFormat};
+use crate::etc1::Etc1Quality;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Filter used to generate each mip level from the previous one.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MipFilter {
+    /// Average of the covered pixels. Soft, but never rings.
+    #[default]
+    Box,
+    /// Kaiser windowed sinc, sharper than box with little ringing.
+    Kaiser,
+    /// Three lobe Lanczos, the sharpest, with some ringing on hard edges.
+    Lanczos,
+}
+
+/// Hardware `TextureMagFilterType` values.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum MagFilter {
+    Linear = 0,
+    Nearest = 1,
+}
+
+/// Hardware `TextureMinFilterType` values.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum MinFilter {
+    Linear = 0,
+    Nearest = 1,
+    NearestMipNearest = 2,
+    NearestMipLinear = 3,
+    LinearMipNearest = 4,
+    LinearMipLinear = 5,
+}
+
+/// Hardware `TextureWrapType` values.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum Wrap {
+    Repeat = 0,
+    Clamp = 1,
+    Mirror = 2,
+    Border = 3,
+}
+
+/// Import settings of one texture or cube map, as read from its manifest.
+///
+/// ```toml
+/// format = "rgb565"
+/// dither = "ordered"
+/// wrap_s = "clamp"
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TextureSettings {
+    /// `None` keeps exactly the channels of the PNG.
+    pub format: Option<TextureFormat>,
+    /// Only used by formats with fewer than 8 bits per channel.
+    pub dither: Dither,
+    /// Only used by [`TextureFormat::Etc1`].
+    pub etc1_quality: Etc1Quality,
+    /// Generates a full mip chain, otherwise only the base level is stored.
+    pub mips: bool,
+    pub mip_filter: MipFilter,
+    /// Filters colors in linear light when generating mips, as PNGs hold
+    /// sRGB encoded colors. Turn it off for textures holding other data.
+    pub srgb: bool,
+    /// Multiplies colors by alpha before generating mips, for shaders that
+    /// blend with a source factor of one.
+    pub premultiply_alpha: bool,
+    /// Scales the alpha of mips so that as many pixels pass an alpha test
+    /// against this threshold as in the base level.
+    pub alpha_test_threshold: Option<f32>,
+    /// Normal maps hold vectors rather than colors: mips are renormalized,
+    /// and `srgb`, `premultiply_alpha` and `alpha_test_threshold` are
+    /// ignored. With `format = "lum_alpha"` only X and Y are stored.
+    pub normal_map: bool,
+    /// Normal maps only: negates Y, converting DirectX style normal maps to
+    /// the OpenGL and glTF convention.
+    pub flip_green: bool,
+    /// Normal maps only: derives Z from X and Y instead of reading the blue
+    /// channel. Two channel PNGs always have Z reconstructed.
+    pub reconstruct_z: bool,
+    pub mag_filter: MagFilter,
+    /// `None` picks `LinearMipLinear` for textures with mips and `Linear`
+    /// for the others.
+    pub min_filter: Option<MinFilter>,
+    /// `None` picks `Repeat` for textures and `Clamp` for cube maps.
+    pub wrap_s: Option<Wrap>,
+    pub wrap_t: Option<Wrap>,
+}
+
+impl Default for TextureSettings {
+    fn default() -> Self {
+        Self {
+            format: None,
+            dither: Dither::default(),
+            etc1_quality: Etc1Quality::default(),
+            mips: true,
+            mip_filter: MipFilter::default(),
+            srgb: true,
+            premultiply_alpha: false,
+            alpha_test_threshold: None,
+            normal_map: false,
+            flip_green: false,
+            reconstruct_z: false,
+            mag_filter: MagFilter::Linear,
+            min_filter: None,
+            wrap_s: None,
+            wrap_t: None,
+        }
+    }
+}
+
+impl TextureSettings {
+    /// Path of the manifest of `png_path`, `name.png.toml`.
+    pub fn manifest_path(png_path: &Path) -> PathBuf {
+        let mut path = png_path.as_os_str().to_owned();
+        path.push(".toml");
+        PathBuf::from(path)
+    }
+
+    /// Reads the manifest at `path`. Settings it doesn't mention keep their
+    /// default value.
+    pub fn read_manifest(path: &Path) -> Result<Self, String> {
+        let err = |err: &dyn std::fmt::Display| format!("{}: {err}", path.display());
+        let text = fs::read_to_string(path).map_err(|e| err(&e))?;
+        toml::from_str(&text).map_err(|e| err(&e))
+    }
+}
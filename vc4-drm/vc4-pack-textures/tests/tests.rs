@@ -0,0 +1,808 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::fs;
+use std::path::{Path, PathBuf};
+use vc4_ctx::CtxHeader;
+use vc4_image_addr::glam::UVec2;
+use vc4_image_addr::{Translator, TranslatorTrait};
+use vc4_pack_textures::*;
+
+const FILTERS: [MipFilter; 3] = [MipFilter::Box, MipFilter::Kaiser, MipFilter::Lanczos];
+
+fn image_from_fn(size: UVec2, format: PixelFormat, f: impl Fn(u32, u32) -> Vec<u8>) -> Image {
+    let mut image = Image::new(size, format);
+    for y in 0..size.y {
+        for x in 0..size.x {
+            image.pixel_mut(x, y).copy_from_slice(&f(x, y));
+        }
+    }
+    image
+}
+
+fn linear_settings(mip_filter: MipFilter) -> TextureSettings {
+    TextureSettings {
+        mip_filter,
+        srgb: false,
+        ..Default::default()
+    }
+}
+
+fn level_sizes(size: UVec2) -> Vec<UVec2> {
+    let pot_size = UVec2::new(size.x.next_power_of_two(), size.y.next_power_of_two());
+    let num_mips = u32::min(size.x.ilog2(), size.y.ilog2()) + 1;
+    (0..num_mips)
+        .map(|level| {
+            if level == 0 {
+                size
+            } else {
+                UVec2::max(UVec2::splat(1), pot_size >> level)
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn mips_constant_npot() {
+    let size = UVec2::new(37, 23);
+    let image = image_from_fn(size, PixelFormat::Rgba, |_, _| vec![200, 100, 30, 255]);
+    for filter in FILTERS {
+        let levels = generate_mips(&image, &level_sizes(size), &linear_settings(filter));
+        assert_eq!(levels.len(), 5);
+        assert_eq!(levels[1].size, UVec2::new(32, 16));
+        for level in &levels {
+            for y in 0..level.size.y {
+                for x in 0..level.size.x {
+                    assert_eq!(level.pixel(x, y), [200, 100, 30, 255], "{filter:?}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn mips_one_pixel_wide() {
+    let size = UVec2::new(1, 16);
+    let image = image_from_fn(size, PixelFormat::Luminance, |_, y| vec![y as u8 * 16]);
+    for filter in FILTERS {
+        let levels = generate_mips(&image, &[size, UVec2::new(1, 8)], &linear_settings(filter));
+        assert_eq!(levels[1].size, UVec2::new(1, 8));
+        // The ramp keeps increasing.
+        for y in 1..8 {
+            assert!(
+                levels[1].pixel(0, y)[0] > levels[1].pixel(0, y - 1)[0],
+                "{filter:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn mips_box_average() {
+    let size = UVec2::new(4, 2);
+    let image = image_from_fn(size, PixelFormat::Luminance, |x, y| {
+        vec![(x * 40 + y * 20) as u8]
+    });
+    let levels = generate_mips(
+        &image,
+        &[size, UVec2::new(2, 1)],
+        &linear_settings(MipFilter::Box),
+    );
+    assert_eq!(levels[1].data, [30, 110]);
+}
+
+#[test]
+fn mips_box_npot_footprint() {
+    // Each of the 2 destination pixels covers 1.5 source pixels.
+    let size = UVec2::new(3, 1);
+    let image = image_from_fn(size, PixelFormat::Luminance, |x, _| {
+        vec![[0, 90, 180][x as usize]]
+    });
+    let levels = generate_mips(
+        &image,
+        &[size, UVec2::new(2, 1)],
+        &linear_settings(MipFilter::Box),
+    );
+    assert_eq!(levels[1].data, [30, 150]);
+}
+
+#[test]
+fn mips_sharp_filters_preserve_mean() {
+    let size = UVec2::new(64, 64);
+    let image = image_from_fn(size, PixelFormat::Luminance, |x, y| {
+        vec![(128.0 + 60.0 * ((x as f32) / 5.0).sin() * ((y as f32) / 7.0).cos()) as u8]
+    });
+    let mean =
+        |image: &Image| image.data.iter().map(|&v| v as f32).sum::<f32>() / image.data.len() as f32;
+    for filter in FILTERS {
+        let levels = generate_mips(&image, &level_sizes(size), &linear_settings(filter));
+        assert!((mean(&levels[1]) - mean(&image)).abs() < 1.5, "{filter:?}");
+    }
+}
+
+#[test]
+fn mips_linear_light() {
+    // A checkerboard of black and white averages to 50% linear light,
+    // which is 188 once sRGB encoded, rather than 128.
+    let size = UVec2::new(2, 2);
+    let image = image_from_fn(size, PixelFormat::Rgb, |x, y| {
+        vec![((x + y) % 2 * 255) as u8; 3]
+    });
+    let sizes = [size, UVec2::new(1, 1)];
+
+    let srgb = generate_mips(&image, &sizes, &TextureSettings::default());
+    assert_eq!(srgb[1].data, [188, 188, 188]);
+
+    let linear = generate_mips(&image, &sizes, &linear_settings(MipFilter::Box));
+    assert_eq!(linear[1].data, [128, 128, 128]);
+}
+
+#[test]
+fn mips_linear_light_keeps_alpha() {
+    let size = UVec2::new(2, 1);
+    let image = image_from_fn(size, PixelFormat::LumAlpha, |x, _| vec![x as u8 * 255; 2]);
+    let levels = generate_mips(
+        &image,
+        &[size, UVec2::new(1, 1)],
+        &TextureSettings::default(),
+    );
+    assert_eq!(levels[1].data, [188, 128]);
+}
+
+#[test]
+fn mips_normal_map_renormalized() {
+    let encode = |v: f32| ((v * 0.5 + 0.5) * 255.0).round() as u8;
+    // Alternating normals tilted 45 degrees left and right average to a
+    // short vector pointing up.
+    let size = UVec2::new(8, 8);
+    let image = image_from_fn(size, PixelFormat::Rgb, |x, _| {
+        let nx = if x % 2 == 0 {
+            FRAC_1_SQRT_2
+        } else {
+            -FRAC_1_SQRT_2
+        };
+        vec![encode(nx), encode(0.0), encode(FRAC_1_SQRT_2)]
+    });
+    let settings = TextureSettings {
+        normal_map: true,
+        ..Default::default()
+    };
+    for filter in FILTERS {
+        let levels = generate_mips(
+            &image,
+            &level_sizes(size),
+            &TextureSettings {
+                mip_filter: filter,
+                ..settings
+            },
+        );
+        for level in &levels[1..] {
+            for pixel in level.data.chunks_exact(3) {
+                let n = pixel.iter().map(|&c| c as f32 / 255.0 * 2.0 - 1.0);
+                let length = n.map(|c| c * c).sum::<f32>().sqrt();
+                assert!((length - 1.0).abs() < 0.02, "{filter:?} {pixel:?}");
+            }
+        }
+    }
+}
+
+#[test]
+fn mips_alpha_coverage() {
+    // Thin vertical lines of opaque pixels, which a box filter would fade
+    // to 25% alpha and make disappear under a 0.5 alpha test.
+    let size = UVec2::new(32, 32);
+    let image = image_from_fn(size, PixelFormat::Rgba, |x, _| {
+        vec![255, 255, 255, if x % 4 == 0 { 255 } else { 0 }]
+    });
+    let coverage = |image: &Image| {
+        let passing = image.data.chunks_exact(4).filter(|p| p[3] >= 128).count();
+        passing as f32 / (image.data.len() / 4) as f32
+    };
+    for filter in FILTERS {
+        let settings = TextureSettings {
+            alpha_test_threshold: Some(0.5),
+            ..linear_settings(filter)
+        };
+        let levels = generate_mips(&image, &level_sizes(size), &settings);
+        for level in &levels[1..4] {
+            assert!(coverage(level) >= 0.25, "{filter:?}");
+        }
+
+        let faded = generate_mips(&image, &level_sizes(size), &linear_settings(filter));
+        assert_eq!(coverage(&faded[2]), 0.0, "{filter:?}");
+    }
+}
+
+fn decode_normal(pixel: &[u8]) -> [f32; 3] {
+    std::array::from_fn(|c| pixel[c] as f32 / 255.0 * 2.0 - 1.0)
+}
+
+#[test]
+fn normals_flip_green() {
+    let image = image_from_fn(UVec2::new(1, 1), PixelFormat::Rgb, |_, _| {
+        vec![128, 218, 218]
+    });
+    let normals = prepare_normals(&image, true, false).unwrap();
+    let n = decode_normal(normals.pixel(0, 0));
+    assert!(n[1] < -0.6 && n[2] > 0.6);
+}
+
+#[test]
+fn normals_reconstruct_z() {
+    // Blue is garbage, X and Y are 0.6 and 0, so Z is 0.8.
+    let encode = |v: f32| ((v * 0.5 + 0.5) * 255.0).round() as u8;
+    let image = image_from_fn(UVec2::new(1, 1), PixelFormat::Rgba, |_, _| {
+        vec![encode(0.6), encode(0.0), 0, 77]
+    });
+    let normals = prepare_normals(&image, false, true).unwrap();
+    let n = decode_normal(normals.pixel(0, 0));
+    assert!((n[2] - 0.8).abs() < 0.01);
+    assert_eq!(normals.pixel(0, 0)[3], 77);
+
+    let two_channel = image_from_fn(UVec2::new(1, 1), PixelFormat::LumAlpha, |_, _| {
+        vec![encode(0.0), encode(0.6)]
+    });
+    let normals = prepare_normals(&two_channel, false, false).unwrap();
+    assert_eq!(normals.format, PixelFormat::Rgb);
+    let n = decode_normal(normals.pixel(0, 0));
+    assert!((n[1] - 0.6).abs() < 0.01 && (n[2] - 0.8).abs() < 0.01);
+
+    let luminance = Image::new(UVec2::new(1, 1), PixelFormat::Luminance);
+    assert!(prepare_normals(&luminance, false, false).is_err());
+}
+
+#[test]
+fn normals_two_channel() {
+    let image = image_from_fn(UVec2::new(2, 1), PixelFormat::Rgb, |x, _| {
+        vec![x as u8 * 10, 20, 30]
+    });
+    let two_channel = normals_to_lum_alpha(&image);
+    assert_eq!(two_channel.format, PixelFormat::LumAlpha);
+    assert_eq!(two_channel.data, [0, 20, 10, 20]);
+}
+
+#[test]
+fn atlas_layout_fits() {
+    let sprites: Vec<_> = [(30, 30), (12, 40), (8, 8), (60, 10), (8, 8)]
+        .iter()
+        .enumerate()
+        .map(|(i, &(w, h))| (format!("sprite{i}"), UVec2::new(w, h)))
+        .collect();
+    let padding = 2;
+    let layout = layout_atlas(&sprites, padding, 2048).unwrap();
+    assert!(layout.size.x.is_power_of_two() && layout.size.y.is_power_of_two());
+    assert_eq!(layout.sprites.len(), sprites.len());
+
+    // Padded cells stay inside the atlas and don't overlap.
+    let cells: Vec<_> = layout
+        .sprites
+        .iter()
+        .map(|s| {
+            (
+                s.x - padding,
+                s.y - padding,
+                s.x + s.width + padding,
+                s.y + s.height + padding,
+            )
+        })
+        .collect();
+    for (i, a) in cells.iter().enumerate() {
+        assert!(a.2 <= layout.size.x && a.3 <= layout.size.y);
+        for b in &cells[i + 1..] {
+            assert!(a.2 <= b.0 || b.2 <= a.0 || a.3 <= b.1 || b.3 <= a.1);
+        }
+    }
+
+    assert!(layout_atlas(&sprites, padding, 32).is_err());
+}
+
+#[test]
+fn atlas_edges_extruded() {
+    let sprite = image_from_fn(UVec2::new(2, 2), PixelFormat::Luminance, |x, y| {
+        vec![(1 + x + y * 2) as u8]
+    });
+    let sprites = [("a".to_string(), sprite)];
+    let layout = layout_atlas(&[("a".to_string(), UVec2::new(2, 2))], 1, 2048).unwrap();
+    assert_eq!(layout.size, UVec2::new(4, 4));
+    let atlas = build_atlas_image(&sprites, &layout, 1);
+    let luminance: Vec<_> = atlas.data.chunks_exact(4).map(|p| p[0]).collect();
+    assert_eq!(luminance, [1, 1, 2, 2, 1, 1, 2, 2, 3, 3, 4, 4, 3, 3, 4, 4]);
+    assert!(atlas.data.chunks_exact(4).all(|p| p[3] == 255));
+}
+
+#[test]
+fn unpack_round_trip() {
+    // NPOT, with both T-format (base) and LT-format (mips) levels.
+    let size = UVec2::new(70, 37);
+    let image = image_from_fn(size, PixelFormat::Rgba, |x, y| {
+        vec![
+            (x * 3) as u8,
+            (y * 6) as u8,
+            (x + y) as u8 * 2,
+            255 - x as u8,
+        ]
+    });
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let png_path = dir.join("unpack_round_trip.png");
+    image.write_png(&png_path).unwrap();
+
+    // Largest channel error of each format, from quantization.
+    let formats = [
+        (TextureFormat::Rgba8888, 0),
+        (TextureFormat::Rgbx8888, 0),
+        (TextureFormat::Rgba4444, 9),
+        (TextureFormat::Rgba5551, 128),
+        (TextureFormat::Rgb565, 5),
+        (TextureFormat::Luminance, 0),
+        (TextureFormat::LumAlpha, 0),
+        (TextureFormat::Etc1, 48),
+    ];
+    for (format, tolerance) in formats {
+        let ctx_path = dir.join(format!("unpack_round_trip_{format:?}.ctx"));
+        let settings = TextureSettings {
+            format: Some(format),
+            ..Default::default()
+        };
+        pack_texture(&png_path, &ctx_path, &settings).unwrap();
+
+        let (header, data) = vc4_ctx::read(std::fs::File::open(&ctx_path).unwrap()).unwrap();
+        assert_eq!(
+            TextureFormat::from_data_type(header.data_type),
+            Some(format)
+        );
+        let layout = format
+            .mip_chain_layout(size, header.num_mips as u32)
+            .unwrap();
+        assert_eq!(layout.total_size, header.data_size);
+        assert_eq!(layout.base_page(), header.mip0_page_offset);
+
+        let base = &layout.levels[0];
+        let base = &data[base.offset as usize..][..base.alloc_size as usize];
+        let unpacked = untile_level(base, size, format);
+        assert_eq!(unpacked.format, format.pixel_format());
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let [r, g, b, a]: [u8; 4] = image.pixel(x, y).try_into().unwrap();
+                let expected = match unpacked.format {
+                    PixelFormat::Luminance => vec![r],
+                    PixelFormat::LumAlpha => vec![r, a],
+                    PixelFormat::Rgb => vec![r, g, b],
+                    PixelFormat::Rgba => vec![r, g, b, a],
+                };
+                for (&got, &expected) in unpacked.pixel(x, y).iter().zip(&expected) {
+                    assert!(
+                        got.abs_diff(expected) <= tolerance,
+                        "{format:?} ({x}, {y}): {got} != {expected}"
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Encodes raw PNG rows, with an optional palette and tRNS chunk.
+fn png_bytes(
+    size: UVec2,
+    color: png::ColorType,
+    depth: png::BitDepth,
+    palette: Option<(&[u8], &[u8])>,
+    data: &[u8],
+) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, size.x, size.y);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    if let Some((palette, trns)) = palette {
+        encoder.set_palette(palette);
+        encoder.set_trns(trns);
+    }
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(data).unwrap();
+    writer.finish().unwrap();
+    bytes
+}
+
+fn write_temp(name: &str, bytes: &[u8]) -> PathBuf {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::write(&path, bytes).unwrap();
+    path
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[test]
+fn png_low_bit_gray_widened() {
+    let bytes = png_bytes(
+        UVec2::new(4, 1),
+        png::ColorType::Grayscale,
+        png::BitDepth::Two,
+        None,
+        &[0b00_01_10_11],
+    );
+    let image = Image::read_png(&write_temp("png_gray2.png", &bytes)).unwrap();
+    assert_eq!(image.format, PixelFormat::Luminance);
+    assert_eq!(image.data, [0, 85, 170, 255]);
+}
+
+#[test]
+fn png_gray_alpha() {
+    let bytes = png_bytes(
+        UVec2::new(2, 1),
+        png::ColorType::GrayscaleAlpha,
+        png::BitDepth::Eight,
+        None,
+        &[10, 200, 20, 100],
+    );
+    let image = Image::read_png(&write_temp("png_gray_alpha.png", &bytes)).unwrap();
+    assert_eq!(image.format, PixelFormat::LumAlpha);
+    assert_eq!(image.data, [10, 200, 20, 100]);
+}
+
+#[test]
+fn png_indexed_with_trns() {
+    // Only the first entry has a tRNS alpha, the rest stay opaque.
+    let palette = [255, 0, 0, 0, 255, 0];
+    let bytes = png_bytes(
+        UVec2::new(2, 2),
+        png::ColorType::Indexed,
+        png::BitDepth::Eight,
+        Some((&palette, &[128])),
+        &[0, 1, 1, 0],
+    );
+    let image = Image::read_png(&write_temp("png_indexed.png", &bytes)).unwrap();
+    assert_eq!(image.format, PixelFormat::Rgba);
+    assert_eq!(image.size, UVec2::new(2, 2));
+    assert_eq!(
+        image.data,
+        [255, 0, 0, 128, 0, 255, 0, 255, 0, 255, 0, 255, 255, 0, 0, 128]
+    );
+}
+
+#[test]
+fn png_16_bit_reduced() {
+    let bytes = png_bytes(
+        UVec2::new(1, 1),
+        png::ColorType::Rgb,
+        png::BitDepth::Sixteen,
+        None,
+        &[0x12, 0x34, 0xab, 0xcd, 0xff, 0xff],
+    );
+    let image = Image::read_png(&write_temp("png_rgb16.png", &bytes)).unwrap();
+    assert_eq!(image.format, PixelFormat::Rgb);
+    assert_eq!(image.data, [0x12, 0xab, 0xff]);
+}
+
+#[test]
+fn png_size_limits() {
+    let bytes = png_bytes(
+        UVec2::new(2049, 1),
+        png::ColorType::Grayscale,
+        png::BitDepth::One,
+        None,
+        &[0; 257],
+    );
+    let path = write_temp("png_oversized.png", &bytes);
+    let err = Image::read_png(&path).unwrap_err();
+    assert!(err.starts_with(&path.display().to_string()), "{err}");
+    assert!(err.contains("2049x1"), "{err}");
+
+    // The encoder refuses zero sizes, so patch the IHDR width and its CRC.
+    let mut bytes = png_bytes(
+        UVec2::new(1, 1),
+        png::ColorType::Grayscale,
+        png::BitDepth::Eight,
+        None,
+        &[0],
+    );
+    bytes[16..20].fill(0);
+    let crc = crc32(&bytes[12..29]);
+    bytes[29..33].copy_from_slice(&crc.to_be_bytes());
+    let path = write_temp("png_zero_width.png", &bytes);
+    let err = Image::read_png(&path).unwrap_err();
+    assert!(err.starts_with(&path.display().to_string()), "{err}");
+}
+
+#[test]
+fn png_errors_name_the_file() {
+    let path = write_temp("png_garbage.png", b"not a png");
+    let err = Image::read_png(&path).unwrap_err();
+    assert!(err.starts_with(&path.display().to_string()), "{err}");
+
+    let missing = Path::new(env!("CARGO_TARGET_TMPDIR")).join("png_missing.png");
+    assert!(Image::read_png(&missing).is_err());
+}
+
+/// Packs `png` with the settings of `manifest` as the only texture of a
+/// fresh resources directory and returns its header and the tiled level 0.
+fn pack_base_level(name: &str, png: &[u8], manifest: &str) -> (CtxHeader, Vec<u8>) {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("texture.png"), png).unwrap();
+    fs::write(dir.join("texture.png.toml"), manifest).unwrap();
+    pack_textures(&dir).unwrap();
+
+    let ctx = fs::File::open(dir.join("generated").join("texture.ctx")).unwrap();
+    let (header, data) = vc4_ctx::read(ctx).unwrap();
+    let base = data[header.mip0_page_offset as usize * 4096..].to_vec();
+    (header, base)
+}
+
+/// 16-bit texel at `(x, y)` of a tiled level, with `y` counted from the top
+/// of the image.
+fn texel_16(level: &[u8], size: UVec2, x: u32, y: u32) -> u16 {
+    let offset = Translator::new(size, 16)
+        .coordinate_to_tile_address(UVec2::new(x, size.y - 1 - y))
+        .offset as usize;
+    u16::from_le_bytes([level[offset], level[offset + 1]])
+}
+
+#[test]
+fn encode_16_bit_texels() {
+    let png = png_bytes(
+        UVec2::ONE,
+        png::ColorType::Rgba,
+        png::BitDepth::Eight,
+        None,
+        &[255, 128, 64, 200],
+    );
+    // Red in the top bits, alpha in the bottom ones.
+    let expected = [
+        ("rgb565", TextureFormat::Rgb565, 0xfc08),
+        ("rgba4444", TextureFormat::Rgba4444, 0xf84c),
+        ("rgba5551", TextureFormat::Rgba5551, 0xfc11),
+    ];
+    for (name, format, word) in expected {
+        let manifest = format!("format = \"{name}\"\nmips = false");
+        let (header, base) = pack_base_level(&format!("encode_texel_{name}"), &png, &manifest);
+        assert_eq!(header.data_type, format.data_type());
+        assert_eq!(texel_16(&base, UVec2::ONE, 0, 0), word, "{format:?}");
+    }
+}
+
+/// Red channel, top row first, of a flat 127 gray image packed as RGB565.
+/// 127 lies between the 5-bit levels 15 (123) and 16 (132).
+fn dithered_red(name: &str, size: UVec2, dither: &str) -> Vec<u8> {
+    let png = png_bytes(
+        size,
+        png::ColorType::Rgb,
+        png::BitDepth::Eight,
+        None,
+        &vec![127; (size.x * size.y * 3) as usize],
+    );
+    let manifest = format!("format = \"rgb565\"\ndither = \"{dither}\"\nmips = false");
+    let (_, base) = pack_base_level(name, &png, &manifest);
+    let mut red = Vec::new();
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let r = (texel_16(&base, size, x, y) >> 11) as u8;
+            red.push((r << 3) | (r >> 2));
+        }
+    }
+    red
+}
+
+#[test]
+fn dither_none_rounds() {
+    let red = dithered_red("dither_none", UVec2::new(4, 4), "none");
+    assert!(red.iter().all(|&r| r == 123));
+}
+
+#[test]
+fn dither_ordered_bayer_pattern() {
+    // Rounds up where the Bayer threshold is 9 or more.
+    let red = dithered_red("dither_ordered", UVec2::new(4, 4), "ordered");
+    #[rustfmt::skip]
+    let expected = [
+        123, 123, 123, 132,
+        132, 123, 132, 123,
+        123, 132, 123, 132,
+        132, 123, 132, 123,
+    ];
+    assert_eq!(red, expected);
+}
+
+#[test]
+fn dither_error_diffusion_mean() {
+    let red = dithered_red("dither_diffusion", UVec2::new(32, 32), "error_diffusion");
+    assert!(red.iter().all(|&r| r == 123 || r == 132));
+    let mean = red.iter().map(|&r| r as f32).sum::<f32>() / red.len() as f32;
+    assert!((mean - 127.0).abs() < 0.5, "{mean}");
+}
+
+/// Empty directory under the test temp dir.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn manifest_keeps_defaults() {
+    let dir = temp_dir("manifest_defaults");
+    let manifest_path = TextureSettings::manifest_path(&dir.join("a.png"));
+    assert_eq!(manifest_path, dir.join("a.png.toml"));
+
+    fs::write(&manifest_path, "").unwrap();
+    let settings = TextureSettings::read_manifest(&manifest_path).unwrap();
+    assert_eq!(settings, TextureSettings::default());
+
+    fs::write(&manifest_path, "format = \"rgb565\"\nmips = false\n").unwrap();
+    let settings = TextureSettings::read_manifest(&manifest_path).unwrap();
+    assert_eq!(
+        settings,
+        TextureSettings {
+            format: Some(TextureFormat::Rgb565),
+            mips: false,
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn manifest_rejects_unknown_fields() {
+    let dir = temp_dir("manifest_unknown");
+    let manifest_path = dir.join("a.png.toml");
+    fs::write(&manifest_path, "formt = \"rgb565\"\n").unwrap();
+    let err = TextureSettings::read_manifest(&manifest_path).unwrap_err();
+    assert!(
+        err.starts_with(&manifest_path.display().to_string()),
+        "{err}"
+    );
+    assert!(err.contains("formt"), "{err}");
+}
+
+#[test]
+fn pack_textures_reads_manifests() {
+    let dir = temp_dir("pack_manifests");
+    let data: Vec<u8> = (0..8)
+        .flat_map(|y| (0..8).flat_map(move |x| [x * 30, y * 30, 0, 255]))
+        .collect();
+    let png = png_bytes(
+        UVec2::new(8, 8),
+        png::ColorType::Rgba,
+        png::BitDepth::Eight,
+        None,
+        &data,
+    );
+    fs::write(dir.join("plain.png"), &png).unwrap();
+    fs::write(dir.join("set.png"), &png).unwrap();
+    fs::write(
+        dir.join("set.png.toml"),
+        "format = \"rgb565\"\nmips = false\nwrap_s = \"clamp\"\n",
+    )
+    .unwrap();
+
+    let inputs = pack_textures(&dir).unwrap();
+    assert_eq!(
+        inputs,
+        [
+            dir.join("plain.png"),
+            dir.join("set.png"),
+            dir.join("set.png.toml"),
+        ]
+    );
+
+    let read_header = |name: &str| {
+        let ctx_path = dir.join("generated").join(name);
+        vc4_ctx::read(fs::File::open(ctx_path).unwrap()).unwrap().0
+    };
+    // Without a manifest, the PNG's own channels with a full mip chain.
+    let plain = read_header("plain.ctx");
+    assert_eq!(plain.data_type, TextureFormat::Rgba8888.data_type());
+    assert_eq!(plain.num_mips, 4);
+    assert_eq!(plain.min_filt, MinFilter::LinearMipLinear as u8);
+    assert_eq!(plain.wrap_s, Wrap::Repeat as u8);
+
+    let set = read_header("set.ctx");
+    assert_eq!(set.data_type, TextureFormat::Rgb565.data_type());
+    assert_eq!(set.num_mips, 1);
+    assert_eq!(set.min_filt, MinFilter::Linear as u8);
+    assert_eq!(set.wrap_s, Wrap::Clamp as u8);
+    assert_eq!(set.wrap_t, Wrap::Repeat as u8);
+}
+
+#[test]
+fn atlas_module_identifiers() {
+    let dir = temp_dir("atlas_module");
+    let atlas_dir = dir.join("ui.atlas");
+    fs::create_dir(&atlas_dir).unwrap();
+    let sprite = png_bytes(
+        UVec2::new(2, 2),
+        png::ColorType::Rgba,
+        png::BitDepth::Eight,
+        None,
+        &[255; 16],
+    );
+    fs::write(atlas_dir.join("a-b.png"), &sprite).unwrap();
+    fs::write(atlas_dir.join("Icon.png"), &sprite).unwrap();
+
+    let module_path = dir.join("atlases.rs");
+    write_atlas_module(&dir, &module_path).unwrap();
+    let source = fs::read_to_string(&module_path).unwrap();
+    assert!(source.contains("pub mod ui {"));
+    assert!(source.contains("pub const A_B: AtlasRect"));
+    assert!(source.contains("pub const ICON: AtlasRect"));
+
+    for (other, colliding) in [("a_b.png", "a-b.png"), ("icon.png", "Icon.png")] {
+        fs::write(atlas_dir.join(other), &sprite).unwrap();
+        let err = write_atlas_module(&dir, &module_path).unwrap_err();
+        assert!(
+            err.contains(&atlas_dir.join(other).display().to_string()),
+            "{err}"
+        );
+        assert!(
+            err.contains(&atlas_dir.join(colliding).display().to_string()),
+            "{err}"
+        );
+        fs::remove_file(atlas_dir.join(other)).unwrap();
+    }
+
+    fs::create_dir(dir.join("UI.atlas")).unwrap();
+    fs::write(dir.join("UI.atlas").join("a.png"), &sprite).unwrap();
+    let err = write_atlas_module(&dir, &module_path).unwrap_err();
+    assert!(err.contains(&atlas_dir.display().to_string()), "{err}");
+    assert!(
+        err.contains(&dir.join("UI.atlas").display().to_string()),
+        "{err}"
+    );
+}
+
+/// Replaces `ctx_path` with a stub of `.ctx` version `version`, packs `dir`
+/// again and returns whether the stub was overwritten.
+fn repacks(dir: &Path, ctx_path: &Path, version: u16) -> bool {
+    let mut stub = vc4_ctx::MAGIC.to_le_bytes().to_vec();
+    stub.extend_from_slice(&version.to_le_bytes());
+    stub.extend_from_slice(b"stub");
+    fs::write(ctx_path, &stub).unwrap();
+    pack_textures(dir).unwrap();
+    fs::read(ctx_path).unwrap() != stub
+}
+
+#[test]
+fn cache_skips_unchanged_outputs() {
+    let dir = temp_dir("cache");
+    let png_path = dir.join("a.png");
+    let manifest_path = dir.join("a.png.toml");
+    let ctx_path = dir.join("generated").join("a.ctx");
+    let flat_png = |v: u8| {
+        let data = [v; 4 * 4 * 3];
+        png_bytes(
+            UVec2::new(4, 4),
+            png::ColorType::Rgb,
+            png::BitDepth::Eight,
+            None,
+            &data,
+        )
+    };
+    fs::write(&png_path, flat_png(10)).unwrap();
+    pack_textures(&dir).unwrap();
+    assert!(ctx_path.exists());
+    assert!(!repacks(&dir, &ctx_path, vc4_ctx::VERSION));
+
+    fs::write(&png_path, flat_png(20)).unwrap();
+    assert!(repacks(&dir, &ctx_path, vc4_ctx::VERSION));
+    assert!(!repacks(&dir, &ctx_path, vc4_ctx::VERSION));
+
+    // Adding a manifest, changing a setting, or only the manifest's text.
+    fs::write(&manifest_path, "mips = true\n").unwrap();
+    assert!(repacks(&dir, &ctx_path, vc4_ctx::VERSION));
+    fs::write(&manifest_path, "mips = false\n").unwrap();
+    assert!(repacks(&dir, &ctx_path, vc4_ctx::VERSION));
+    fs::write(&manifest_path, "# base level only\nmips = false\n").unwrap();
+    assert!(repacks(&dir, &ctx_path, vc4_ctx::VERSION));
+    assert!(!repacks(&dir, &ctx_path, vc4_ctx::VERSION));
+
+    // Outputs written by an older packer, even with the same inputs.
+    assert!(repacks(&dir, &ctx_path, vc4_ctx::VERSION - 1));
+    let header = vc4_ctx::read(fs::File::open(&ctx_path).unwrap()).unwrap().0;
+    assert_eq!(header.num_mips, 1);
+}